@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fmt;
+
+/// Position in template source an error points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// Template file name, `None` for templates rendered from a string
+    pub template: Option<String>,
+    /// Byte offset from the template start
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number, in chars
+    pub column: usize,
+    /// Template line containing the error, without line break
+    pub source_line: String,
+}
+
+impl Location {
+    pub fn from_source(template: Option<String>, source: &[u8], offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let line_start = source[..offset]
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |pos| pos + 1);
+        let line_end = source[offset..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(source.len(), |pos| offset + pos);
+
+        let line = source[..line_start]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count()
+            + 1;
+        let column = String::from_utf8_lossy(&source[line_start..offset])
+            .chars()
+            .count()
+            + 1;
+        let source_line = String::from_utf8_lossy(&source[line_start..line_end])
+            .trim_end_matches('\r')
+            .to_string();
+
+        Self {
+            template,
+            offset,
+            line,
+            column,
+            source_line,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// Tag or block is missing its closing delimiter
+    UnterminatedTag {
+        expected: String,
+        location: Location,
+    },
+    /// Pipe name doesn't match any of the known pipes
+    UnknownPipe { name: String, location: Location },
+    /// Variable (or one of its path properties) isn't defined in any scope
+    UndefinedVariable { name: String, location: Location },
+    /// Value has unexpected type, e.g. a loop over text
+    TypeMismatch { message: String, location: Location },
+    /// Malformed tag, e.g. invalid pipe parameters or misplaced else branch
+    Syntax { message: String, location: Location },
+    /// Inline asset, partial or layout can't be included
+    Include { message: String, location: Location },
+}
+
+impl TemplateError {
+    pub fn location(&self) -> &Location {
+        match self {
+            TemplateError::UnterminatedTag { location, .. }
+            | TemplateError::UnknownPipe { location, .. }
+            | TemplateError::UndefinedVariable { location, .. }
+            | TemplateError::TypeMismatch { location, .. }
+            | TemplateError::Syntax { location, .. }
+            | TemplateError::Include { location, .. } => location,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            TemplateError::UnterminatedTag { expected, .. } => {
+                format!("unterminated tag, expected closing `{}`", expected)
+            }
+            TemplateError::UnknownPipe { name, .. } => format!("unknown pipe `{}`", name),
+            TemplateError::UndefinedVariable { name, .. } => {
+                format!("variable `{}` is undefined", name)
+            }
+            TemplateError::TypeMismatch { message, .. }
+            | TemplateError::Syntax { message, .. }
+            | TemplateError::Include { message, .. } => message.clone(),
+        }
+    }
+}
+
+/// Formats error the way rustc does:
+///
+/// ```text
+/// error: variable `nam` is undefined
+///  --> index.rustache:3:9
+///   |
+/// 3 |     <h1>{{ nam }}</h1>
+///   |         ^
+/// ```
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = self.location();
+        let line = location.line.to_string();
+        let gutter = " ".repeat(line.len());
+        // keep tabs so the caret is aligned the same way as the source line
+        let padding = location
+            .source_line
+            .chars()
+            .take(location.column - 1)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        writeln!(f, "error: {}", self.message())?;
+
+        match &location.template {
+            Some(template) => writeln!(
+                f,
+                "{}--> {}:{}:{}",
+                gutter, template, location.line, location.column
+            )?,
+            None => writeln!(f, "{}--> {}:{}", gutter, location.line, location.column)?,
+        }
+
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line, location.source_line)?;
+        write!(f, "{} | {}^", gutter, padding)
+    }
+}
+
+impl Error for TemplateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_should_point_at_line_and_column() {
+        let source = "<div>\n  <p>{{ ünïcode }}</p>\n</div>".as_bytes();
+        let location = Location::from_source(None, source, 16);
+
+        assert_eq!(location.line, 2);
+        assert_eq!(location.column, 10);
+        assert_eq!(location.source_line, "  <p>{{ ünïcode }}</p>");
+    }
+
+    #[test]
+    fn template_error_should_display_source_snippet() {
+        let source = "<div>\n    <h1>{{ nam }}</h1>\n</div>".as_bytes();
+        let error = TemplateError::UndefinedVariable {
+            name: "nam".to_string(),
+            location: Location::from_source(Some("index.rustache".to_string()), source, 14),
+        };
+
+        assert_eq!(
+            error.to_string(),
+            "\
+error: variable `nam` is undefined
+ --> index.rustache:2:9
+  |
+2 |     <h1>{{ nam }}</h1>
+  |         ^"
+        );
+    }
+}
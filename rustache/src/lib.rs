@@ -0,0 +1,12 @@
+//! Rustache - a home-made HTML template engine, rendering `.rustache` templates
+//! with RON (Rustache Object Notation) variables
+
+mod error;
+mod pipe;
+pub mod ron;
+mod rustache;
+mod template;
+
+pub use crate::error::{Location, TemplateError};
+pub use crate::rustache::{render, render_str, FsResolver, Renderer, Resolver, Result};
+pub use crate::template::{Template, UndefinedMode};
@@ -1,8 +1,4 @@
-mod ron;
-mod rustache;
-mod pipe;
-
-use std::{env, path::Path};
+use std::{env, path::Path, process};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -18,16 +14,22 @@ fn main() {
         "Expected output path argument e.g.: `--out=./build/index.html`",
     );
 
-    match rustache::render(input, output) {
-        Ok(_) => println!("[rustache] Successfully rendered template into {}", output.display()),
-        Err(error) => println!("[rustache] Failed to render template: {error}"),
+    match mt_rustache::render(input, output) {
+        Ok(_) => println!(
+            "[rustache] Successfully rendered template into {}",
+            output.display()
+        ),
+        Err(error) => {
+            eprintln!("[rustache] Failed to render template\n\n{error}");
+            process::exit(1);
+        }
     }
 }
 
-fn read_path_arg<'a>(args: &'a Vec<String>, name: &'a str, err_msg: &'a str) -> &'a Path {
+fn read_path_arg<'a>(args: &'a [String], name: &'a str, err_msg: &'a str) -> &'a Path {
     args.iter()
         .find(|a| a.starts_with(&format!("--{name}")))
-        .and_then(|a| a.split('=').last())
+        .and_then(|a| a.split('=').next_back())
         .map(Path::new)
         .expect(err_msg)
 }
@@ -1,13 +1,21 @@
 use once_cell::sync::Lazy;
+use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt::Debug;
 use std::ops::RangeBounds;
 use std::result;
 
-use crate::ron::Value;
+use crate::ron::{self, Value};
 
 pub type Result<T> = result::Result<T, Box<dyn Error>>;
 
+const REVERSE_PIPE_NAME: &str = "$reverse";
+const SORT_PIPE_NAME: &str = "$sort";
+
+pub fn is_known(name: &str) -> bool {
+    matches!(name, REVERSE_PIPE_NAME | SORT_PIPE_NAME)
+}
+
 pub fn parse(value: &str) -> Result<Box<dyn Pipe>> {
     let (name, params) = match value.split_once(' ') {
         None => (value.to_string(), "".to_string()),
@@ -15,8 +23,8 @@ pub fn parse(value: &str) -> Result<Box<dyn Pipe>> {
     };
 
     match name.as_str() {
-        "$reverse" => Ok(Box::new(ReversePipe::from_string(params)?)),
-        "$sort" => Ok(Box::new(SortPipe::from_string(params)?)),
+        REVERSE_PIPE_NAME => Ok(Box::new(ReversePipe::from_string(params)?)),
+        SORT_PIPE_NAME => Ok(Box::new(SortPipe::from_string(params)?)),
         _ => Err(format!("Unexpected pipe name: {:?}", name))?,
     }
 }
@@ -39,9 +47,12 @@ impl Pipe for ReversePipe {
 
     fn apply(&self, value: &Value) -> Result<Value> {
         match value {
-            Value::Text(text) => Ok(Value::Text(text.chars().rev().collect())), // Note: broken for surrogate pairs
             Value::Array(array) => Ok(Value::Array(array.clone().into_iter().rev().collect())),
-            _ => Err(format!("Can't apply ReversePipe to {:?}", value))?,
+            // scalars are reversed as their text, e.g. `12345` as `54321`
+            _ => match value.to_text() {
+                Some(text) => Ok(Value::Text(text.chars().rev().collect())), // Note: broken for surrogate pairs
+                None => Err(format!("Can't apply ReversePipe to {:?}", value))?,
+            },
         }
     }
 }
@@ -71,7 +82,7 @@ impl Pipe for SortPipe {
                         right.first().unwrap().as_str(),
                     ) {
                         ("$1", "$2") | ("$2", "$1") => (),
-                        _ => Err(format!("Expected argument names to start with $1 or $2"))?,
+                        _ => Err("Expected argument names to start with $1 or $2")?,
                     }
 
                     Ok(Self { op, left, right })
@@ -82,33 +93,39 @@ impl Pipe for SortPipe {
                 ))?,
             },
             Err(err) => Err(format!("Can't parse $sort expression {:?}", err))?,
-            _ => Err(format!("Unexpected $sort expression type"))?,
+            _ => Err("Unexpected $sort expression type")?,
         }
     }
 
     fn apply(&self, value: &Value) -> Result<Value> {
         match value {
             Value::Array(array) => {
-                let mut result = array.clone();
-
-                result.sort_by(|l, r| {
-                    let mut l_value = self.get_value(l, &self.left[1..]).unwrap();
-                    let mut r_value = self.get_value(r, &self.right[1..]).unwrap();
-
-                    if self.left.first().unwrap() == "$2" {
-                        (l_value, r_value) = (r_value, l_value);
-                    }
-
-                    match self.op {
-                        Op::IntCmp => u32::from_str_radix(&l_value, 10)
-                            .unwrap()
-                            .cmp(&u32::from_str_radix(&r_value, 10).unwrap()),
-                        Op::StrCmp => l_value.cmp(&r_value),
-                        _ => panic!("Unexpected operation"),
+                // keys are read (and checked) upfront, as sorting can't fail
+                let mut keyed = array
+                    .iter()
+                    .map(|item| {
+                        Ok((
+                            self.get_key(item, &self.left[1..])?,
+                            self.get_key(item, &self.right[1..])?,
+                            item,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                keyed.sort_by(|l, r| {
+                    let (l_item, r_item) = if self.left[0] == "$1" { (l, r) } else { (r, l) };
+
+                    match (&l_item.0, &r_item.1) {
+                        (Value::Number(l_key), Value::Number(r_key)) => {
+                            l_key.partial_cmp(r_key).unwrap_or(Ordering::Equal)
+                        }
+                        (l_key, r_key) => l_key.to_text().cmp(&r_key.to_text()),
                     }
                 });
 
-                Ok(Value::Array(result))
+                Ok(Value::Array(
+                    keyed.into_iter().map(|(_, _, item)| item.clone()).collect(),
+                ))
             }
             _ => Err(format!(
                 "Can't apply SortPipe to {:?} (expected array)",
@@ -119,15 +136,25 @@ impl Pipe for SortPipe {
 }
 
 impl SortPipe {
-    fn get_value(&self, value: &Value, path: &[String]) -> Result<String> {
-        match (value, &path[..]) {
-            (Value::Object(v), [key, rest @ ..]) => self.get_value(
+    /// Reads sort key, which is a number for `$int_cmp`
+    /// and any non-null scalar (compared as text) for `$str_cmp`
+    fn get_key(&self, value: &Value, path: &[String]) -> Result<Value> {
+        match (value, path, &self.op) {
+            (Value::Object(v), [key, rest @ ..], _) => self.get_key(
                 v.get(key)
                     .ok_or(format!("Property {} is undefined at {:?}", key, value))?,
                 rest,
             ),
-            (Value::Text(v), []) => Ok(v.to_string()),
-            _ => Err(format!("Can't read {:?} at {:?}", path, value))?,
+            (Value::Number(_), [], Op::IntCmp) => Ok(value.clone()),
+            (Value::Bool(_) | Value::Number(_) | Value::Text(_), [], Op::StrCmp) => {
+                Ok(value.clone())
+            }
+            (_, [], Op::IntCmp) => Err(format!("Expected number to sort by, got {:?}", value))?,
+            (_, [], Op::StrCmp) => Err(format!("Expected text to sort by, got {:?}", value))?,
+            (_, _, Op::IntCmp | Op::StrCmp) => {
+                Err(format!("Can't read {:?} at {:?}", path, value))?
+            }
+            (_, _, op) => Err(format!("Unexpected $sort operation {:?}", op))?,
         }
     }
 }
@@ -151,7 +178,7 @@ impl ParserState {
         self.in_bytes[self.pos]
     }
 
-    fn advance(&mut self) -> () {
+    fn advance(&mut self) {
         self.pos += 1
     }
 
@@ -163,17 +190,28 @@ impl ParserState {
 type PR<T> = Result<Option<T>>;
 
 #[derive(Debug, PartialEq, Eq)]
-enum Op {
+pub enum Op {
     IntCmp,
     StrCmp,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    And,
+    Or,
+    Not,
+    Exists,
     Unknown,
 }
 
 type Parser<T> = Box<dyn Fn(&mut PS) -> PR<T> + Sync + Send>;
 
-#[derive(Debug, PartialEq, Eq)]
-enum Expr {
+/// Condition operands are `"quoted"` text, numbers, `true`, `false` and `null` literals
+/// or variable paths (any other word)
+#[derive(Debug, PartialEq)]
+pub enum Expr {
     Id(String),
+    Literal(Value),
     Call(Op, Vec<Expr>),
 }
 
@@ -257,6 +295,164 @@ impl Expr {
             None => Err("Failed to parse expression")?,
         }
     }
+
+    /// Parses Optional block condition, e.g. `$it.count > 2 and not $it.hidden`
+    ///
+    /// Operators from the lowest precedence: `or`, `and`, `not` and `exists`,
+    /// comparisons `==`, `!=`, `<` and `>`; operands are variable paths,
+    /// bare words or `"quoted text"`, and can be grouped with parentheses
+    pub fn from_condition(value: &str) -> Result<Self> {
+        let mut state = PS::from_string(value);
+
+        let expr = parse_or(&mut state)?;
+        SPACES(&mut state)?;
+
+        match expr {
+            Some(expr) if state.is_at_end() => Ok(expr),
+            _ => Err(format!(
+                "Unexpected condition syntax at {}: {:?}",
+                state.pos,
+                String::from_utf8_lossy(&state.in_bytes[state.pos.min(state.in_bytes.len())..])
+            ))?,
+        }
+    }
+}
+
+const KEYWORDS: [&str; 4] = ["or", "and", "not", "exists"];
+
+static SPACES: Lazy<Parser<Vec<u8>>> =
+    Lazy::new(|| parser_many(parser_byte_ranges(vec![(b' '..=b' '), (b'\t'..=b'\r')])));
+
+static WORD: Lazy<Parser<Vec<u8>>> = Lazy::new(|| {
+    parser_many(parser_or(vec![
+        parser_byte_ranges(vec![
+            (b'0'..=b'9'),
+            (b'a'..=b'z'),
+            (b'A'..=b'Z'),
+            (0x80..=0xff),
+        ]),
+        parser_byte(b'$'),
+        parser_byte(b'_'),
+        parser_byte(b'.'),
+        parser_byte(b'-'),
+    ]))
+});
+
+static COMPARISON: Lazy<Parser<Op>> = Lazy::new(|| {
+    parser_or(vec![
+        parser_map(parser_str("=="), |_| Op::Eq),
+        parser_map(parser_str("!="), |_| Op::Ne),
+        parser_map(parser_str("<"), |_| Op::Lt),
+        parser_map(parser_str(">"), |_| Op::Gt),
+    ])
+});
+
+fn parse_or(state: &mut PS) -> PR<Expr> {
+    let mut left = try_parse!(parse_and(state));
+
+    while parse_keyword(state, "or")? {
+        let right = try_parse!(parse_and(state));
+        left = Expr::Call(Op::Or, vec![left, right]);
+    }
+
+    Ok(Some(left))
+}
+
+fn parse_and(state: &mut PS) -> PR<Expr> {
+    let mut left = try_parse!(parse_not(state));
+
+    while parse_keyword(state, "and")? {
+        let right = try_parse!(parse_not(state));
+        left = Expr::Call(Op::And, vec![left, right]);
+    }
+
+    Ok(Some(left))
+}
+
+fn parse_not(state: &mut PS) -> PR<Expr> {
+    if parse_keyword(state, "not")? {
+        let expr = try_parse!(parse_not(state));
+        return Ok(Some(Expr::Call(Op::Not, vec![expr])));
+    }
+
+    if parse_keyword(state, "exists")? {
+        return match try_parse!(parse_operand(state)) {
+            id @ Expr::Id(_) => Ok(Some(Expr::Call(Op::Exists, vec![id]))),
+            _ => Err("Expected variable name after exists")?,
+        };
+    }
+
+    parse_comparison(state)
+}
+
+fn parse_comparison(state: &mut PS) -> PR<Expr> {
+    let left = try_parse!(parse_operand(state));
+    SPACES(state)?;
+
+    match COMPARISON(state)? {
+        Some(op) => {
+            let right = try_parse!(parse_operand(state));
+            Ok(Some(Expr::Call(op, vec![left, right])))
+        }
+        None => Ok(Some(left)),
+    }
+}
+
+fn parse_operand(state: &mut PS) -> PR<Expr> {
+    SPACES(state)?;
+
+    if state.is_at_end() {
+        return Ok(None);
+    }
+
+    match state.peek() {
+        b'(' => {
+            state.advance();
+            let expr = try_parse!(parse_or(state));
+            SPACES(state)?;
+            try_parse!(parser_byte(b')')(state));
+
+            Ok(Some(expr))
+        }
+        b'"' => {
+            state.advance();
+            let text = try_parse!(parser_many(parser_byte_ranges(vec![
+                (0..=b'"' - 1),
+                (b'"' + 1..=0xff)
+            ]))(state));
+            try_parse!(parser_byte(b'"')(state));
+
+            Ok(Some(Expr::Literal(Value::Text(String::from_utf8(text)?))))
+        }
+        _ => {
+            let initial_pos = state.pos;
+            let word = String::from_utf8(try_parse!(WORD(state)))?;
+
+            if word.is_empty() || KEYWORDS.contains(&word.as_str()) {
+                state.pos = initial_pos;
+                return Ok(None);
+            }
+
+            match ron::parse_literal(&word) {
+                Value::Text(_) => Ok(Some(Expr::Id(word))),
+                literal => Ok(Some(Expr::Literal(literal))),
+            }
+        }
+    }
+}
+
+/// Consumes `keyword` if it is the next word
+fn parse_keyword(state: &mut PS, keyword: &str) -> Result<bool> {
+    let initial_pos = state.pos;
+    SPACES(state)?;
+
+    match WORD(state)? {
+        Some(word) if word == keyword.as_bytes() => Ok(true),
+        _ => {
+            state.pos = initial_pos;
+            Ok(false)
+        }
+    }
 }
 
 fn parser_byte_ranges(ranges: Vec<impl RangeBounds<u8> + 'static + Sync + Send>) -> Parser<u8> {
@@ -280,6 +476,10 @@ fn parser_byte(ch: u8) -> Parser<u8> {
     parser_byte_ranges(vec![(ch..=ch)])
 }
 
+fn parser_str(value: &'static str) -> Parser<Vec<u8>> {
+    parser_seq(value.bytes().map(parser_byte).collect())
+}
+
 fn parser_seq<T, P>(parsers: Vec<P>) -> Parser<Vec<T>>
 where
     P: Fn(&mut PS) -> PR<T> + 'static + Sync + Send,
@@ -386,6 +586,16 @@ mod tests {
         assert_eq!(pipe.apply(&value).unwrap(), expected)
     }
 
+    #[test]
+    fn reverse_pipe_should_reverse_scalar_text() {
+        let pipe = parse("$reverse").unwrap();
+        let value = ron::parse_literal("1.50");
+        let expected = Value::Text("05.1".to_string());
+
+        assert_eq!(pipe.apply(&value).unwrap(), expected);
+        assert!(pipe.apply(&Value::Object(ron::Map::new())).is_err());
+    }
+
     #[test]
     fn test_parser_byte() {
         let mut state = ParserState::from_string("123abc!$#");
@@ -426,7 +636,7 @@ mod tests {
         if let Ok(None) = result {
             assert_eq!(state.pos, 0);
         } else {
-            assert!(false);
+            panic!("Expected parser to fail");
         }
     }
 
@@ -480,6 +690,54 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_condition() {
+        let result = Expr::from_condition(
+            "$it.count > 2 and not ($it.name == \"Iron Maiden\" or exists $it.hidden)",
+        );
+
+        assert_eq!(
+            result.unwrap(),
+            Expr::Call(
+                Op::And,
+                vec![
+                    Expr::Call(
+                        Op::Gt,
+                        vec![
+                            Expr::Id("$it.count".to_string()),
+                            Expr::Literal(Value::Number(ron::Number::int(2)))
+                        ]
+                    ),
+                    Expr::Call(
+                        Op::Not,
+                        vec![Expr::Call(
+                            Op::Or,
+                            vec![
+                                Expr::Call(
+                                    Op::Eq,
+                                    vec![
+                                        Expr::Id("$it.name".to_string()),
+                                        Expr::Literal(Value::Text("Iron Maiden".to_string()))
+                                    ]
+                                ),
+                                Expr::Call(Op::Exists, vec![Expr::Id("$it.hidden".to_string())])
+                            ]
+                        )]
+                    )
+                ]
+            )
+        )
+    }
+
+    #[test]
+    fn test_condition_failed() {
+        assert!(Expr::from_condition("a ==").is_err());
+        assert!(Expr::from_condition("a and").is_err());
+        assert!(Expr::from_condition("(a or b").is_err());
+        assert!(Expr::from_condition("a b").is_err());
+        assert!(Expr::from_condition("").is_err());
+    }
+
     #[test]
     fn test_sort_pipe_sub() {
         let pipe = parse("$sort ($int_cmp $2.count.value $1.count.value)").unwrap();
@@ -543,6 +801,40 @@ mod tests {
         assert_eq!(pipe.apply(&value).unwrap(), expected)
     }
 
+    #[test]
+    fn test_sort_pipe_numbers() {
+        let pipe = parse("$sort ($int_cmp $1.value $2.value)").unwrap();
+
+        let value =
+            ron::parse("[\n{\n value: 10\n}\n{\n value: -2.5\n}\n{\n value: 9\n}\n]".to_string())
+                .unwrap();
+        let expected =
+            ron::parse("[\n{\n value: -2.5\n}\n{\n value: 9\n}\n{\n value: 10\n}\n]".to_string())
+                .unwrap();
+
+        assert_eq!(pipe.apply(&value).unwrap(), expected)
+    }
+
+    #[test]
+    fn test_sort_pipe_failed() {
+        let pipe = parse("$sort ($int_cmp $1.value $2.value)").unwrap();
+
+        for (value, message) in [
+            (
+                "[\n{\n value: 10\n}\n{\n value: ten\n}\n]",
+                "Expected number to sort by, got Text(\"ten\")",
+            ),
+            (
+                "[\n{\n value: 10\n}\n{\n other: 5\n}\n]",
+                "Property value is undefined at Object({\"other\": Number(Int(5))})",
+            ),
+        ] {
+            let value = ron::parse(value.to_string()).unwrap();
+
+            assert_eq!(pipe.apply(&value).unwrap_err().to_string(), message);
+        }
+    }
+
     #[test]
     fn test_sort_pipe_str_cmp() {
         let pipe = parse("$sort ($str_cmp $2.count.value $1.count.value)").unwrap();
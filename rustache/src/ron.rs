@@ -1,6 +1,9 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::error::Error;
-use std::iter::Peekable;
+use std::fmt;
+use std::iter::{FromIterator, Peekable};
+use std::ops::Index;
 use std::slice::Iter;
 use std::{result, vec};
 
@@ -10,6 +13,20 @@ const ARRAY_OPEN: u8 = b'[';
 const ARRAY_CLOSE: u8 = b']';
 const ID_CLOSE: u8 = b':';
 const ID_CLOSE_ESCAPE: &str = r"\:";
+// comments start at token start or after whitespace,
+// so `c#` or `https\://` are still plain text
+const LINE_COMMENT_OPENS: [&[u8]; 2] = [b"#", b"//"];
+const BLOCK_COMMENT_OPEN: &[u8] = b"/*";
+const BLOCK_COMMENT_CLOSE: &[u8] = b"*/";
+const COMMENT_ESCAPES: [&str; 2] = [r"\#", r"\/"];
+// quoted text keeps spaces and braces as is, and supports `\n`, `\t`, `\r`, `\"`, `\\`
+// and `\u{1F600}` escapes, `"""` quoted text can span multiple lines
+const QUOTE: &[u8] = b"\"";
+const MULTILINE_QUOTE: &[u8] = b"\"\"\"";
+const ESCAPE: char = '\\';
+const TRUE: &str = "true";
+const FALSE: &str = "false";
+const NULL: &str = "null";
 
 pub type Result<T> = result::Result<T, Box<dyn Error>>;
 
@@ -24,11 +41,435 @@ pub fn parse(value: String) -> Result<Value> {
     Ok(result)
 }
 
+/// Serializes value into RON string, which [`parse`] reads back into the same value.
+/// Nested values are written one per line, without indentation
+pub fn to_string(value: &Value) -> Result<String> {
+    to_string_pretty(value, "")
+}
+
+/// Serializes value into RON string like [`to_string`],
+/// indenting nested values with `indent`, e.g. 4 spaces
+pub fn to_string_pretty(value: &Value, indent: &str) -> Result<String> {
+    let mut result = String::new();
+    write_value(&mut result, value, indent, 0)?;
+
+    Ok(result)
+}
+
+fn write_value(out: &mut String, value: &Value, indent: &str, depth: usize) -> Result<()> {
+    match value {
+        Value::Null => out.push_str(NULL),
+        Value::Bool(value) => out.push_str(if *value { TRUE } else { FALSE }),
+        Value::Number(value) if value.as_f64().is_finite() => out.push_str(&value.to_string()),
+        Value::Number(value) => Err(format!("Can't serialize {} number", value))?,
+        Value::Text(text) => write_text(out, text),
+        Value::Array(array) if array.is_empty() => {
+            out.extend([ARRAY_OPEN as char, ARRAY_CLOSE as char])
+        }
+        Value::Array(array) => {
+            out.extend([ARRAY_OPEN as char, '\n']);
+
+            for item in array {
+                out.push_str(&indent.repeat(depth + 1));
+                write_value(out, item, indent, depth + 1)?;
+                out.push('\n');
+            }
+
+            out.push_str(&indent.repeat(depth));
+            out.push(ARRAY_CLOSE as char);
+        }
+        Value::Object(object) if object.is_empty() => {
+            out.extend([OBJECT_OPEN as char, OBJECT_CLOSE as char])
+        }
+        Value::Object(object) => {
+            out.extend([OBJECT_OPEN as char, '\n']);
+
+            for (key, value) in object {
+                if !is_valid_key(key) {
+                    Err(format!("Can't serialize object key {:?}", key))?
+                }
+
+                out.push_str(&indent.repeat(depth + 1));
+                out.push_str(key);
+                out.extend([ID_CLOSE as char, ' ']);
+                write_value(out, value, indent, depth + 1)?;
+                out.push('\n');
+            }
+
+            out.push_str(&indent.repeat(depth));
+            out.push(OBJECT_CLOSE as char);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes text as is if lexer reads it back as text, escaping `:` and comment starts,
+/// or quoted otherwise
+fn write_text(out: &mut String, text: &str) {
+    if !is_bare_text(text) {
+        return write_quoted(out, text);
+    }
+
+    let mut prev = None;
+
+    for (pos, ch) in text.char_indices() {
+        let is_token_start = !matches!(prev, Some(prev) if !char::is_ascii_whitespace(&prev));
+
+        if ch == ID_CLOSE as char {
+            out.push_str(ID_CLOSE_ESCAPE);
+        } else if is_token_start && is_comment_start(&text.as_bytes()[pos..]) {
+            out.extend([ESCAPE, ch]);
+        } else {
+            out.push(ch);
+        }
+
+        prev = Some(ch);
+    }
+}
+
+fn write_quoted(out: &mut String, text: &str) {
+    out.push('"');
+
+    for ch in text.chars() {
+        match ch {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '"' | ESCAPE => out.extend([ESCAPE, ch]),
+            ch if ch.is_control() => out.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+
+    out.push('"');
+}
+
+/// Checks that text can be written without quotes,
+/// i.e. it's single line, not trimmed by lexer and not a typed literal
+fn is_bare_text(text: &str) -> bool {
+    let bytes = text.as_bytes();
+
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) => {
+            !first.is_ascii_whitespace()
+                && !last.is_ascii_whitespace()
+                && ![OBJECT_OPEN, OBJECT_CLOSE, ARRAY_OPEN, ARRAY_CLOSE, QUOTE[0]].contains(first)
+                && !bytes
+                    .iter()
+                    .any(|&byte| byte.is_ascii_control() || byte == ESCAPE as u8)
+                && matches!(parse_literal(text), Value::Text(_))
+        }
+        _ => false,
+    }
+}
+
+/// Checks that key is read back as object key,
+/// keys can't have escapes so `:` and comment starts aren't allowed
+fn is_valid_key(key: &str) -> bool {
+    let bytes = key.as_bytes();
+
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) => {
+            !first.is_ascii_whitespace()
+                && ![OBJECT_OPEN, OBJECT_CLOSE, ARRAY_OPEN, ARRAY_CLOSE, QUOTE[0]].contains(first)
+                && *last != ESCAPE as u8
+                && !bytes
+                    .iter()
+                    .any(|&byte| byte.is_ascii_control() || byte == ID_CLOSE)
+                && !(0..bytes.len()).any(|pos| {
+                    (pos == 0 || bytes[pos - 1].is_ascii_whitespace())
+                        && is_comment_start(&bytes[pos..])
+                })
+        }
+        _ => false,
+    }
+}
+
+fn is_comment_start(bytes: &[u8]) -> bool {
+    bytes.starts_with(BLOCK_COMMENT_OPEN)
+        || LINE_COMMENT_OPENS
+            .iter()
+            .any(|open| bytes.starts_with(open))
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
     Text(String),
     Array(Vec<Value>),
-    Object(HashMap<String, Value>),
+    Object(Map),
+}
+
+impl Value {
+    /// Text representation of scalar values, `None` for arrays and objects
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            Value::Null => Some(String::new()),
+            Value::Bool(value) => Some(value.to_string()),
+            Value::Number(value) => Some(value.to_string()),
+            Value::Text(value) => Some(value.clone()),
+            Value::Array(_) | Value::Object(_) => None,
+        }
+    }
+}
+
+/// Object entries in the order their keys were first inserted,
+/// with keys indexed for O(1) lookup
+#[derive(Default, Clone)]
+pub struct Map {
+    entries: Vec<(String, Value)>,
+    indices: HashMap<String, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.indices.get(key).map(|&index| &self.entries[index].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        match self.indices.get(key) {
+            Some(&index) => Some(&mut self.entries[index].1),
+            None => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    /// Replaces the value of an existing key in place, keeping its position,
+    /// or appends a new entry, returning the previous value if any
+    pub fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        match self.indices.get(&key) {
+            Some(&index) => Some(std::mem::replace(&mut self.entries[index].1, value)),
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Entries in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+/// Maps are equal if they have the same entries, regardless of their order
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Index<&str> for Map {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        self.get(key)
+            .unwrap_or_else(|| panic!("Key {:?} is not in map", key))
+    }
+}
+
+impl Extend<(String, Value)> for Map {
+    fn extend<T: IntoIterator<Item = (String, Value)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl FromIterator<(String, Value)> for Map {
+    fn from_iter<T: IntoIterator<Item = (String, Value)>>(iter: T) -> Self {
+        let mut map = Map::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<const N: usize> From<[(String, Value); N]> for Map {
+    fn from(entries: [(String, Value); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (String, Value);
+    type IntoIter = vec::IntoIter<(String, Value)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Map {
+    type Item = (&'a String, &'a Value);
+    type IntoIter =
+        std::iter::Map<Iter<'a, (String, Value)>, fn(&'a (String, Value)) -> Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+/// Number keeping the text it's written with in the source, e.g. `1.10` or `1e3`,
+/// which is used to render it, while the parsed value is used to compare numbers
+#[derive(Clone)]
+pub struct Number {
+    value: NumberValue,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NumberValue {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    pub fn int(value: i64) -> Self {
+        Number {
+            value: NumberValue::Int(value),
+            text: value.to_string(),
+        }
+    }
+
+    /// Float written in debug format, which always has `.` or exponent,
+    /// so the number is read back as float
+    pub fn float(value: f64) -> Self {
+        Number {
+            value: NumberValue::Float(value),
+            text: format!("{:?}", value),
+        }
+    }
+
+    /// Integer value, `None` for floats
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.value {
+            NumberValue::Int(value) => Some(value),
+            NumberValue::Float(_) => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match self.value {
+            NumberValue::Int(value) => value as f64,
+            NumberValue::Float(value) => value,
+        }
+    }
+}
+
+/// Integers are compared exactly, anything else as floats
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.value, other.value) {
+            (NumberValue::Int(left), NumberValue::Int(right)) => Some(left.cmp(&right)),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+}
+
+/// Numbers are equal by value, regardless of how they are written, e.g. `1.10` and `1.1`
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl fmt::Debug for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            NumberValue::Int(_) => write!(f, "Int({})", self.text),
+            NumberValue::Float(_) => write!(f, "Float({})", self.text),
+        }
+    }
+}
+
+/// Writes number as it's written in the source
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Parses unquoted literal, `true`, `false`, `null` and numbers (e.g. `-12`, `0.5`, `1e3`)
+/// are typed, anything else (including numbers with leading zeros like `007`) is text
+pub fn parse_literal(value: &str) -> Value {
+    match value {
+        TRUE => Value::Bool(true),
+        FALSE => Value::Bool(false),
+        NULL => Value::Null,
+        _ if !is_number(value) => Value::Text(value.to_string()),
+        _ if value.contains(['.', 'e', 'E']) => match value.parse() {
+            Ok(parsed) => Value::Number(Number {
+                value: NumberValue::Float(parsed),
+                text: value.to_string(),
+            }),
+            Err(_) => Value::Text(value.to_string()),
+        },
+        // integers not fitting into i64 are kept as text to not lose precision
+        _ => match value.parse() {
+            Ok(parsed) => Value::Number(Number {
+                value: NumberValue::Int(parsed),
+                text: value.to_string(),
+            }),
+            Err(_) => Value::Text(value.to_string()),
+        },
+    }
+}
+
+fn is_number(value: &str) -> bool {
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+
+    let unsigned = value.strip_prefix('-').unwrap_or(value);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+
+    is_digits(integer)
+        && (integer == "0" || !integer.starts_with('0'))
+        && fraction.into_iter().all(is_digits)
+        && exponent
+            .into_iter()
+            .all(|exponent| is_digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)))
 }
 
 #[derive(Debug)]
@@ -49,23 +490,33 @@ impl<'a> Parser<'a> {
             .next()
             .ok_or("Unexpected end of tokens stream")?;
 
-        return match token {
+        match token {
             Token::ObjectOpen => self.run_object(),
             Token::ArrayOpen => self.run_array(),
             Token::Text(value) => self.run_text(value),
+            Token::Quoted(value) => Ok(Value::Text(value.clone())),
             _ => Err(format!("Expected Object, Array or Text, got: {:?}", token))?,
-        };
+        }
     }
 
     fn run_text(&mut self, value: &[u8]) -> Result<Value> {
-        let string = String::from_utf8(value.to_vec())?
-            .replace(ID_CLOSE_ESCAPE, &(ID_CLOSE as char).to_string());
+        let string = String::from_utf8(value.to_vec())?;
+
+        // escaped values are never typed literals
+        let mut string = match parse_literal(&string) {
+            Value::Text(string) => string.replace(ID_CLOSE_ESCAPE, &(ID_CLOSE as char).to_string()),
+            value => return Ok(value),
+        };
+
+        for escape in COMMENT_ESCAPES {
+            string = string.replace(escape, &escape[1..]);
+        }
 
         Ok(Value::Text(string))
     }
 
     fn run_object(&mut self) -> Result<Value> {
-        let mut object = HashMap::new();
+        let mut object = Map::new();
 
         loop {
             let token = self.tokens.next();
@@ -114,6 +565,7 @@ impl<'a> Parser<'a> {
 enum Token<'a> {
     Id(&'a [u8]),
     Text(&'a [u8]),
+    Quoted(String),
     ObjectOpen,
     ObjectClose,
     ArrayOpen,
@@ -145,12 +597,18 @@ impl<'a> Lexer<'a> {
                 OBJECT_CLOSE => self.emit(Token::ObjectClose, 1),
                 ARRAY_OPEN => self.emit(Token::ArrayOpen, 1),
                 ARRAY_CLOSE => self.emit(Token::ArrayClose, 1),
+                _ if self.is_comment_at(self.pos) => self.skip_comment()?,
+                _ if self.bytes[self.pos..].starts_with(QUOTE) => {
+                    let value = self.read_quoted()?;
+                    self.tokens.push(Token::Quoted(value));
+                }
                 byte if (!byte.is_ascii_whitespace()) => {
                     let start = self.pos;
                     let mut end;
                     let mut is_eof;
                     let mut is_id_close;
                     let mut is_text_close;
+                    let mut is_comment;
 
                     loop {
                         end = self.pos + 1;
@@ -158,23 +616,39 @@ impl<'a> Lexer<'a> {
                         is_id_close = !is_eof
                             && self.bytes[end] == ID_CLOSE
                             && &self.bytes[end - 1..end + 1] != ID_CLOSE_ESCAPE.as_bytes();
+                        is_comment = !is_eof
+                            && self.bytes[end - 1].is_ascii_whitespace()
+                            && self.is_comment_at(end);
                         is_text_close = !is_eof && self.bytes[end].is_ascii_control();
 
                         self.advance(1);
 
-                        if is_eof || is_id_close || is_text_close {
+                        if is_eof || is_id_close || is_text_close || is_comment {
                             break;
                         }
                     }
 
-                    let value = &self.bytes[start..end];
+                    let mut value = &self.bytes[start..end];
+
+                    // whitespace separating text from the comment isn't part of it
+                    if is_comment {
+                        while let [rest @ .., last] = value {
+                            if !last.is_ascii_whitespace() {
+                                break;
+                            }
+
+                            value = rest;
+                        }
+                    }
+
                     let token = if is_id_close {
                         Token::Id(value)
                     } else {
                         Token::Text(value)
                     };
 
-                    self.emit(token, 1)
+                    // comment is skipped on its own
+                    self.emit(token, usize::from(!is_comment))
                 }
                 _ => self.advance(1),
             }
@@ -183,16 +657,143 @@ impl<'a> Lexer<'a> {
         Ok(&self.tokens)
     }
 
-    fn advance(&mut self, n: usize) -> () {
+    fn is_comment_at(&self, pos: usize) -> bool {
+        is_comment_start(&self.bytes[pos..])
+    }
+
+    /// Skips line comment until the line end, or block comment until its closing
+    fn skip_comment(&mut self) -> Result<()> {
+        let rest = &self.bytes[self.pos..];
+
+        if rest.starts_with(BLOCK_COMMENT_OPEN) {
+            let len = rest[BLOCK_COMMENT_OPEN.len()..]
+                .windows(BLOCK_COMMENT_CLOSE.len())
+                .position(|window| window == BLOCK_COMMENT_CLOSE)
+                .ok_or("Expected */ closing block comment")?;
+
+            self.advance(BLOCK_COMMENT_OPEN.len() + len + BLOCK_COMMENT_CLOSE.len());
+        } else {
+            let len = rest
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .unwrap_or(rest.len());
+
+            self.advance(len);
+        }
+
+        Ok(())
+    }
+
+    /// Reads `"quoted"` or `"""multi-line"""` text, resolving its escapes
+    fn read_quoted(&mut self) -> Result<String> {
+        let is_multiline = self.bytes[self.pos..].starts_with(MULTILINE_QUOTE);
+        let quote = if is_multiline { MULTILINE_QUOTE } else { QUOTE };
+        self.advance(quote.len());
+        let start = self.pos;
+
+        loop {
+            match self.bytes.get(self.pos) {
+                None => Err(format!(
+                    "Expected {} closing quoted text",
+                    String::from_utf8_lossy(quote)
+                ))?,
+                Some(b'\n') if !is_multiline => {
+                    Err("Unexpected line break in quoted text, use \"\"\" for multi-line text")?
+                }
+                Some(&byte) if byte == ESCAPE as u8 => self.advance(2),
+                Some(_) if self.bytes[self.pos..].starts_with(quote) => break,
+                Some(_) => self.advance(1),
+            }
+        }
+
+        let text = String::from_utf8(self.bytes[start..self.pos].to_vec())?;
+        self.advance(quote.len());
+
+        if is_multiline {
+            unescape(&strip_indentation(&text))
+        } else {
+            unescape(&text)
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
         self.pos += n;
     }
 
-    fn emit(&mut self, token: Token<'a>, advance_n: usize) -> () {
+    fn emit(&mut self, token: Token<'a>, advance_n: usize) {
         self.tokens.push(token);
         self.advance(advance_n);
     }
 }
 
+/// Strips line break after opening `"""`, blank line before closing one
+/// and indentation common to all non-blank lines
+fn strip_indentation(text: &str) -> String {
+    let mut lines = text
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .collect::<Vec<_>>();
+
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
+    }
+
+    if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+        lines.pop();
+    }
+
+    let indentation = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| line.get(indentation..).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn unescape(text: &str) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != ESCAPE {
+            result.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(ch @ ('"' | ESCAPE)) => result.push(ch),
+            Some('u') => {
+                let code = chars
+                    .as_str()
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .map(|(code, _)| code)
+                    .ok_or("Expected \\u{...} unicode escape")?;
+                let ch = u32::from_str_radix(code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("Invalid unicode escape \\u{{{}}}", code))?;
+
+                result.push(ch);
+                chars.nth(code.len() + 1);
+            }
+            Some(ch) => Err(format!("Unexpected escape sequence \\{}", ch))?,
+            None => Err("Unexpected end of quoted text after \\")?,
+        }
+    }
+
+    Ok(result)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -219,13 +820,198 @@ mod tests {
     }
 
     #[test]
-    fn parse_object() {
+    fn parse_comments() {
+        let string = "
+# gigs are counted by hand
+{
+    // kept in sync with the site
+    text: Name // trailing comment
+    language: c# /* block comment */
+    /*
+    commented: out
+    */
+    array: [
+        ArrayText # trailing comment
+        /* inline */ Other
+    ]
+}
+"
+        .to_string();
+
+        let expected = Value::Object(Map::from([
+            ("text".to_string(), Value::Text("Name".to_string())),
+            ("language".to_string(), Value::Text("c#".to_string())),
+            (
+                "array".to_string(),
+                Value::Array(vec![
+                    Value::Text("ArrayText".to_string()),
+                    Value::Text("Other".to_string()),
+                ]),
+            ),
+        ]));
+
+        assert_eq!(parse(string).unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_escaped_comments() {
         assert_eq!(
-            parse("{}".to_string()).unwrap(),
-            Value::Object(HashMap::new())
+            parse(r"\#hashtag and \// slashes".to_string()).unwrap(),
+            Value::Text("#hashtag and // slashes".to_string())
         );
     }
 
+    #[test]
+    fn parse_unterminated_block_comment() {
+        let error = parse("{\n  /* text: Name\n}".to_string()).unwrap_err();
+
+        assert_eq!(error.to_string(), "Expected */ closing block comment");
+    }
+
+    #[test]
+    fn parse_quoted_text() {
+        let string = r##"
+{
+    spaced: "  {not an object}  "
+    empty: ""
+    escaped: "say \"hi\"\tand\\or\nwave \u{1F44B}"
+    comment: "# not a comment" # comment
+}
+"##
+        .to_string();
+
+        let expected = Value::Object(Map::from([
+            (
+                "spaced".to_string(),
+                Value::Text("  {not an object}  ".to_string()),
+            ),
+            ("empty".to_string(), Value::Text("".to_string())),
+            (
+                "escaped".to_string(),
+                Value::Text("say \"hi\"\tand\\or\nwave 👋".to_string()),
+            ),
+            (
+                "comment".to_string(),
+                Value::Text("# not a comment".to_string()),
+            ),
+        ]));
+
+        assert_eq!(parse(string).unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_multiline_text() {
+        let string = r#"
+[
+    """
+        First paragraph: "quoted"
+          indented
+
+        Last \u{2014} line
+        """
+    """single line"""
+]
+"#
+        .to_string();
+
+        let expected = Value::Array(vec![
+            Value::Text(
+                "First paragraph: \"quoted\"\n  indented\n\nLast \u{2014} line".to_string(),
+            ),
+            Value::Text("single line".to_string()),
+        ]);
+
+        assert_eq!(parse(string).unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_invalid_quoted_text() {
+        for (string, message) in [
+            (r#""unterminated"#, r#"Expected " closing quoted text"#),
+            (
+                "\"line\nbreak\"",
+                r#"Unexpected line break in quoted text, use """ for multi-line text"#,
+            ),
+            (r#""\x""#, r"Unexpected escape sequence \x"),
+            (r#""\u{110000}""#, r"Invalid unicode escape \u{110000}"),
+            (r#""""unterminated"#, r#"Expected """ closing quoted text"#),
+        ] {
+            assert_eq!(parse(string.to_string()).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn parse_typed_literals() {
+        let string = r#"
+[
+    42
+    -7
+    0.5
+    -1.5e3
+    true
+    false
+    null
+    007
+    "42"
+    1.2.3
+    99999999999999999999
+    -
+]
+"#
+        .to_string();
+
+        let expected = Value::Array(vec![
+            Value::Number(Number::int(42)),
+            Value::Number(Number::int(-7)),
+            Value::Number(Number::float(0.5)),
+            Value::Number(Number::float(-1500.0)),
+            Value::Bool(true),
+            Value::Bool(false),
+            Value::Null,
+            Value::Text("007".to_string()),
+            Value::Text("42".to_string()),
+            Value::Text("1.2.3".to_string()),
+            Value::Text("99999999999999999999".to_string()),
+            Value::Text("-".to_string()),
+        ]);
+
+        assert_eq!(parse(string).unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_numbers_keeping_their_text() {
+        let string = "
+[
+    1.10
+    -1.5E3
+    -0
+]
+"
+        .to_string();
+
+        let texts = match parse(string).unwrap() {
+            Value::Array(array) => array
+                .iter()
+                .map(|value| value.to_text())
+                .collect::<Vec<_>>(),
+            value => panic!("Expected array, got {:?}", value),
+        };
+
+        assert_eq!(
+            texts,
+            [Some("1.10"), Some("-1.5E3"), Some("-0")].map(|text| text.map(str::to_string))
+        );
+        assert_eq!(
+            to_string(&parse("1.10".to_string()).unwrap()).unwrap(),
+            "1.10"
+        );
+    }
+
+    #[test]
+    fn parse_object() {
+        assert_eq!(parse("{}".to_string()).unwrap(), Value::Object(Map::new()));
+    }
+
     #[test]
     fn parse_nested() {
         let string = "
@@ -243,7 +1029,7 @@ mod tests {
 "
         .to_string();
 
-        let expected = Value::Object(HashMap::from([
+        let expected = Value::Object(Map::from([
             ("text".to_string(), Value::Text("Name".to_string())),
             (
                 "array".to_string(),
@@ -251,7 +1037,7 @@ mod tests {
             ),
             (
                 "object".to_string(),
-                Value::Object(HashMap::from([(
+                Value::Object(Map::from([(
                     "field".to_string(),
                     Value::Text("ObjectText".to_string()),
                 )])),
@@ -260,4 +1046,168 @@ mod tests {
 
         assert_eq!(parse(string).unwrap(), expected);
     }
+
+    #[test]
+    fn parse_object_in_key_order() {
+        let string = "
+{
+    zeta: 1
+    alpha: 2
+    mid: 3
+    alpha: 4
+}
+"
+        .to_string();
+
+        let object = match parse(string).unwrap() {
+            Value::Object(object) => object,
+            value => panic!("Expected object, got {:?}", value),
+        };
+
+        assert_eq!(object.keys().collect::<Vec<_>>(), ["zeta", "alpha", "mid"]);
+        assert_eq!(object["alpha"], Value::Number(Number::int(4)));
+        assert_eq!(object.get("omega"), None);
+    }
+
+    #[test]
+    fn serialize_pretty() {
+        let value = Value::Object(Map::from([
+            ("name".to_string(), Value::Text("Wall: Part 2".to_string())),
+            ("count".to_string(), Value::Number(Number::int(3))),
+            (
+                "tags".to_string(),
+                Value::Array(vec![
+                    Value::Bool(true),
+                    Value::Null,
+                    Value::Number(Number::float(1.0)),
+                    Value::Array(vec![]),
+                ]),
+            ),
+            ("links".to_string(), Value::Object(Map::new())),
+        ]));
+
+        let expected = r"{
+    name: Wall\: Part 2
+    count: 3
+    tags: [
+        true
+        null
+        1.0
+        []
+    ]
+    links: {}
+}";
+
+        assert_eq!(to_string_pretty(&value, "    ").unwrap(), expected);
+        assert_eq!(parse(expected.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn serialize_escaped_text() {
+        let value = Value::Array(vec![
+            Value::Text("c# /not/ a comment".to_string()),
+            Value::Text("# // /* comment".to_string()),
+            Value::Text("42".to_string()),
+            Value::Text("true".to_string()),
+            Value::Text("".to_string()),
+            Value::Text(" {padded} ".to_string()),
+            Value::Text("line\n\"quoted\" \\ \u{0}".to_string()),
+        ]);
+
+        let expected = r#"[
+c# /not/ a comment
+\# \// \/* comment
+"42"
+"true"
+""
+" {padded} "
+"line\n\"quoted\" \\ \u{0}"
+]"#;
+
+        assert_eq!(to_string(&value).unwrap(), expected);
+        assert_eq!(parse(expected.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn serialize_invalid_values() {
+        let key = Value::Object(Map::from([("a:b".to_string(), Value::Null)]));
+        let number = Value::Number(Number::float(f64::NAN));
+
+        assert!(to_string(&key).is_err());
+        assert!(to_string(&number).is_err());
+    }
+
+    /// Xorshift generator, so property tests are reproducible without dependencies
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+
+        fn text(&mut self, pool: &[char]) -> String {
+            (0..self.below(8))
+                .map(|_| pool[self.below(pool.len())])
+                .collect()
+        }
+
+        fn value(&mut self, depth: usize) -> Value {
+            const TEXT_POOL: [char; 30] = [
+                'a', 'b', ':', '#', '/', '*', '\\', '"', '{', '}', '[', ']', ' ', '\t', '\n', 'é',
+                '👋', '\u{0}', '\u{85}', '0', '1', '7', '-', '.', 'e', 't', 'r', 'u', 'n', 'l',
+            ];
+            const KEY_POOL: [char; 8] = ['a', 'z', '0', '9', '_', '-', '$', 'é'];
+
+            match self.below(if depth < 3 { 8 } else { 6 }) {
+                0 => Value::Null,
+                1 => Value::Bool(self.next() & 1 == 0),
+                2 => Value::Number(Number::int(self.next() as i64 >> self.below(64))),
+                3 => match f64::from_bits(self.next()) {
+                    float if float.is_finite() => Value::Number(Number::float(float)),
+                    _ => Value::Number(Number::float(-0.25)),
+                },
+                4 | 5 => Value::Text(self.text(&TEXT_POOL)),
+                6 => Value::Array((0..self.below(4)).map(|_| self.value(depth + 1)).collect()),
+                _ => Value::Object(
+                    (0..self.below(4))
+                        .map(|_| {
+                            let key = format!("k{}", self.text(&KEY_POOL));
+                            (key, self.value(depth + 1))
+                        })
+                        .collect(),
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..1000 {
+            let value = random.value(0);
+
+            for string in [
+                to_string(&value).unwrap(),
+                to_string_pretty(&value, "  ").unwrap(),
+                to_string_pretty(&value, "\t").unwrap(),
+            ] {
+                let parsed = parse(string.clone()).unwrap();
+
+                assert_eq!(parsed, value, "{}", string);
+                // equality ignores key order, so check it by serializing again
+                assert_eq!(
+                    to_string_pretty(&parsed, "\t").unwrap(),
+                    to_string_pretty(&value, "\t").unwrap()
+                );
+            }
+        }
+    }
 }
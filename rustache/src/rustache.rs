@@ -1,466 +1,2916 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::path::Path;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::{fs, result};
 
-use crate::pipe::{self};
+use crate::error::{Location, TemplateError};
+use crate::pipe::{self, Expr, Pipe};
 use crate::ron;
 use crate::ron::Value as RonValue;
+use crate::template::{Node, Source, Span, Template, UndefinedMode, Variable};
 
 pub type Result<T> = result::Result<T, Box<dyn Error>>;
+type TemplateResult<T> = result::Result<T, TemplateError>;
 
-type TemplatePair = (u8, u8);
-
-const TEMPLATE_NAME: &str = "index.rustache";
+const TEMPLATE_NAME: &str = "index";
+const TEMPLATE_EXTENSION: &str = "rustache";
 const VARIABLES_NAME: &str = "index.ron";
 const CSS_NAME: &str = "index.css";
 const JS_NAME: &str = "index.js";
+const PARTIAL_PREFIX: &str = "partial:";
 const LOOP_ITEM_VARIABLE: &str = "$it";
-const VARIABLE_OPEN: TemplatePair = (b'{', b'{');
-const VARIABLE_CLOSE: TemplatePair = (b'}', b'}');
-const LOOP_OPEN: TemplatePair = (b'{', b'*');
-const LOOP_CLOSE: TemplatePair = (b'*', b'}');
-const OPTIONAL_OPEN: TemplatePair = (b'{', b'?');
-const OPTIONAL_CLOSE: TemplatePair = (b'?', b'}');
-const INLINE_OPEN: TemplatePair = (b'{', b'>');
-const INLINE_CLOSE: TemplatePair = (b'<', b'}');
-const BLOCK_END: TemplatePair = (b'{', b'}');
-const VARIABLE_PATH_SEPARATOR: char = '.';
+const LOOP_ALIAS_KEYWORD: &str = "as";
+// tags are made of delimiters (`{` and `}` by default) and sigils,
+// e.g. `{* items *}` is a loop with `*` opening and closing sigils
+const VARIABLE_OPEN: u8 = b'{';
+const VARIABLE_CLOSE: &[u8] = b"}";
+const RAW_VARIABLE_OPEN: u8 = b'{';
+const RAW_VARIABLE_CLOSE: &[u8] = b"}}";
+const LOOP_OPEN: u8 = b'*';
+const LOOP_CLOSE: &[u8] = b"*";
+const OPTIONAL_OPEN: u8 = b'?';
+const OPTIONAL_CLOSE: &[u8] = b"?";
+const INLINE_OPEN: u8 = b'>';
+const INLINE_CLOSE: &[u8] = b"<";
+const NEGATED_OPEN: u8 = b'!';
+const NEGATED_CLOSE: &[u8] = b"!";
+const NAMED_BLOCK_OPEN: u8 = b'+';
+const NAMED_BLOCK_CLOSE: &[u8] = b"+";
+const LAYOUT_OPEN: u8 = b'^';
+const LAYOUT_CLOSE: &[u8] = b"^";
+const COMMENT_OPEN: u8 = b'#';
+const COMMENT_CLOSE: &[u8] = b"#";
+const DELIMITERS_OPEN: u8 = b'=';
+const DELIMITERS_CLOSE: &[u8] = b"=";
+// markers are placed between delimiters, e.g. `{:}`
+const BLOCK_END: &[u8] = b"";
+const BLOCK_END_TRIM: &[u8] = b"-";
+const OPTIONAL_ELSE: &[u8] = b":";
+const OPTIONAL_ELSE_TRIM: &[u8] = b"-:";
+const RAW_OPEN: &[u8] = b"raw";
+const RAW_CLOSE: &[u8] = b"/raw";
+const TRIM_MARKER: u8 = b'-';
+// `\{{` renders literal `{{`, `\\{{` renders `\` followed by the tag,
+// backslashes not followed by a tag or a marker (e.g. `\{x`) are rendered as is
+const ESCAPE: u8 = b'\\';
+const DEFAULT_OPEN_DELIMITER: &str = "{";
+const DEFAULT_CLOSE_DELIMITER: &str = "}";
 const PIPE_SEPARATOR: char = '|';
 
-const BLOCK_OPENING_PAIRS: [TemplatePair; 2] = [LOOP_OPEN, OPTIONAL_OPEN];
-
+const BLOCK_OPENING_SIGILS: [u8; 4] = [LOOP_OPEN, OPTIONAL_OPEN, NEGATED_OPEN, NAMED_BLOCK_OPEN];
+const TAG_SIGILS: [u8; 9] = [
+    VARIABLE_OPEN,
+    LOOP_OPEN,
+    OPTIONAL_OPEN,
+    NEGATED_OPEN,
+    INLINE_OPEN,
+    NAMED_BLOCK_OPEN,
+    LAYOUT_OPEN,
+    COMMENT_OPEN,
+    DELIMITERS_OPEN,
+];
+const MARKERS: [&[u8]; 6] = [
+    BLOCK_END,
+    BLOCK_END_TRIM,
+    OPTIONAL_ELSE,
+    OPTIONAL_ELSE_TRIM,
+    RAW_OPEN,
+    RAW_CLOSE,
+];
+
+/// Renders `index.rustache` template from the input directory
+/// with `index.ron` variables into the output file
 pub fn render(input: &Path, output: &Path) -> Result<()> {
-    let mut parser = Parser::from(input)?;
-    parser.run()?;
-    let result = parser.result()?;
+    let resolver = FsResolver::new(input);
+    let template = resolver.resolve(&template_file_name(TEMPLATE_NAME))?;
+    let variables = ron::parse(resolver.resolve(VARIABLES_NAME)?)?;
+
+    let template = Renderer::new()
+        .name(template_file_name(TEMPLATE_NAME))
+        .resolver(resolver)
+        .compile(&template)?;
+
+    // output is rendered into a temporary file replacing it only on success,
+    // so a failed render doesn't leave a partial page behind
+    let temp_output = temp_path(output);
+    let rendered = File::create(&temp_output)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|file| template.render_to(&variables, BufWriter::new(file)));
+
+    match rendered {
+        Ok(_) => Ok(fs::rename(&temp_output, output)?),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_output);
+            Err(err)
+        }
+    }
+}
+
+/// Hidden file next to the given one, e.g. `build/.index.html.tmp`
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+
+    path.with_file_name(name)
+}
+
+/// Renders template string with given variables,
+/// template can't use inline assets, partials or layouts
+pub fn render_str(template: &str, data: &RonValue) -> TemplateResult<String> {
+    Renderer::new().render(template, data)
+}
+
+/// Provides inline assets (`{> css <}`, `{> js <}`), partials and layouts
+/// by their file name e.g. `index.css` or `header.rustache`
+pub trait Resolver {
+    fn resolve(&self, name: &str) -> Result<String>;
+}
+
+/// Reads files from the input directory
+#[derive(Debug)]
+pub struct FsResolver {
+    dir: PathBuf,
+}
+
+impl FsResolver {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
 
-    fs::write(output, result)?;
+impl Resolver for FsResolver {
+    fn resolve(&self, name: &str) -> Result<String> {
+        let path = self.dir.join(name);
+
+        fs::read_to_string(&path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err).into())
+    }
+}
 
-    Ok(())
+/// Serves files from memory, keyed by file name
+impl Resolver for HashMap<String, String> {
+    fn resolve(&self, name: &str) -> Result<String> {
+        self.get(name)
+            .cloned()
+            .ok_or_else(|| format!("File {} is not found", name).into())
+    }
 }
 
 #[derive(Debug)]
+struct NoResolver;
+
+impl Resolver for NoResolver {
+    fn resolve(&self, name: &str) -> Result<String> {
+        Err(format!("Can't resolve {}: no resolver configured", name))?
+    }
+}
+
+/// Template renderer, configured with builder methods:
+///
+/// ```
+/// # use mt_rustache::{ron, Renderer};
+/// # use std::collections::HashMap;
+/// let files = HashMap::from([("header.rustache".to_string(), "<h1>{{ name }}</h1>".to_string())]);
+/// let data = ron::parse("{\n name: Test name\n}".to_string()).unwrap();
+///
+/// let result = Renderer::new()
+///     .resolver(files)
+///     .render("{> partial:header <}", &data)
+///     .unwrap();
+///
+/// assert_eq!(result, "<h1>Test name</h1>");
+/// ```
+pub struct Renderer {
+    name: Option<String>,
+    resolver: Box<dyn Resolver>,
+    delimiters: Delimiters,
+    undefined: UndefinedMode,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            resolver: Box::new(NoResolver),
+            delimiters: Delimiters::default(),
+            undefined: UndefinedMode::default(),
+        }
+    }
+
+    /// Template name used in error locations
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    /// Tag delimiters replacing `{` and `}`, e.g. `<%` and `%>` for `<%{ name }%>`,
+    /// templates (and partials) can still override them with `{= <% %> =}` directive
+    /// at the very top
+    pub fn delimiters(mut self, open: impl Into<String>, close: impl Into<String>) -> Self {
+        self.delimiters = Delimiters {
+            open: open.into().into_bytes(),
+            close: close.into().into_bytes(),
+        };
+        self
+    }
+
+    /// Undefined variables handling, strict by default
+    pub fn undefined(mut self, mode: UndefinedMode) -> Self {
+        self.undefined = mode;
+        self
+    }
+
+    /// Compiles template once, resolving its partials, layouts and inline assets,
+    /// to render it with different variables later
+    pub fn compile(&self, template: &str) -> TemplateResult<Template> {
+        Parser::new(
+            self.resolver.as_ref(),
+            self.name.clone(),
+            &self.delimiters,
+            template,
+        )?
+        .compile(self.undefined)
+    }
+
+    pub fn render(&self, template: &str, data: &RonValue) -> TemplateResult<String> {
+        self.compile(template)?.render(data)
+    }
+
+    /// Renders template into `writer` as it goes, see [`Template::render_to`]
+    pub fn render_to(
+        &self,
+        template: &str,
+        data: &RonValue,
+        writer: impl Write,
+    ) -> Result<Vec<TemplateError>> {
+        self.compile(template)?.render_to(data, writer)
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn template_file_name(name: &str) -> String {
+    format!("{}.{}", name, TEMPLATE_EXTENSION)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Delimiters {
+    open: Vec<u8>,
+    close: Vec<u8>,
+}
+
+impl Delimiters {
+    fn parse(value: &str) -> result::Result<Self, String> {
+        match value.split_whitespace().collect::<Vec<_>>()[..] {
+            [open, close] => Ok(Self {
+                open: open.as_bytes().to_vec(),
+                close: close.as_bytes().to_vec(),
+            }),
+            _ => Err(format!(
+                "expected opening and closing delimiters separated by space, got `{}`",
+                value
+            )),
+        }
+    }
+
+    fn validate(&self) -> result::Result<(), String> {
+        let is_valid = |delimiter: &[u8]| {
+            !delimiter.is_empty() && !delimiter.iter().any(|byte| byte.is_ascii_whitespace())
+        };
+
+        if is_valid(&self.open) && is_valid(&self.close) {
+            Ok(())
+        } else {
+            Err(format!(
+                "invalid delimiters `{}` and `{}`, expected non-empty delimiters without whitespace",
+                String::from_utf8_lossy(&self.open),
+                String::from_utf8_lossy(&self.close)
+            ))
+        }
+    }
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Self {
+            open: DEFAULT_OPEN_DELIMITER.as_bytes().to_vec(),
+            close: DEFAULT_CLOSE_DELIMITER.as_bytes().to_vec(),
+        }
+    }
+}
+
 struct Parser<'a> {
-    input: &'a Path,
+    resolver: &'a dyn Resolver,
+    name: Option<String>,
+    // delimiters set by renderer, used by templates without directive
+    default_delimiters: &'a Delimiters,
+    delimiters: Delimiters,
     in_bytes: Vec<u8>,
-    out_bytes: Vec<u8>,
     pos: usize,
-    scopes: Vec<RonValue>,
+    tag_pos: usize,
+    blocks: usize,
+    partials: Vec<String>,
+    // blocks of extending templates, compiled in place of the layout ones
+    overrides: HashMap<String, BlockOverride>,
+    // templates, layouts and partials compiled so far, nodes refer to them by index
+    sources: Vec<Source>,
+    source: usize,
+}
+
+/// Named block of extending template, its body starts at `pos` in `source`
+struct BlockOverride {
+    source: usize,
+    start: usize,
+    pos: usize,
+    delimiters: Delimiters,
 }
 
 impl<'a> Parser<'a> {
-    fn from(input: &'a Path) -> Result<Self> {
-        let template_path = input.join(TEMPLATE_NAME);
-        let template = fs::read_to_string(&template_path)?;
-        let variables_path = input.join(VARIABLES_NAME);
-        let variables_string = fs::read_to_string(&variables_path)?;
+    fn new(
+        resolver: &'a dyn Resolver,
+        name: Option<String>,
+        delimiters: &'a Delimiters,
+        template: &str,
+    ) -> TemplateResult<Self> {
+        let mut parser = Self {
+            resolver,
+            name,
+            default_delimiters: delimiters,
+            delimiters: delimiters.clone(),
+            in_bytes: template.as_bytes().to_vec(),
+            pos: 0,
+            tag_pos: 0,
+            blocks: 0,
+            partials: vec![],
+            overrides: HashMap::new(),
+            sources: vec![],
+            source: 0,
+        };
+
+        parser.add_source(parser.name.clone());
+        parser
+            .delimiters
+            .validate()
+            .map_err(|err| parser.syntax_error(0, err))?;
+        parser.read_delimiters()?;
+        parser.resolve_layouts()?;
+
+        Ok(parser)
+    }
+
+    /// Switches delimiters if template starts with `{= open close =}` directive,
+    /// moving past it
+    fn read_delimiters(&mut self) -> TemplateResult<()> {
+        let start = self.pos;
+        self.skip_whitespace();
+
+        if self.peek_tag() != Some(DELIMITERS_OPEN) {
+            self.pos = start;
+            return Ok(());
+        }
+
+        self.tag_pos = self.pos;
+        self.skip_tag_open();
+        let value = self.skip_until_close(DELIMITERS_CLOSE)?;
+        self.skip_tag_close(DELIMITERS_CLOSE);
+
+        let delimiters = Delimiters::parse(&value)
+            .and_then(|delimiters| delimiters.validate().map(|_| delimiters))
+            .map_err(|err| self.syntax_error(self.tag_pos, err))?;
+        self.delimiters = delimiters;
+
+        Ok(())
+    }
+
+    /// Replaces template extending a layout (`{^ layout ^}` at the very top)
+    /// with that layout, keeping named blocks (`{+ name +} ... {}`) defined by template
+    /// to compile in place of the layout ones. Layouts may extend other layouts
+    fn resolve_layouts(&mut self) -> TemplateResult<()> {
+        let mut layouts: Vec<String> = vec![];
+
+        while let Some(layout) = self.read_layout_name()? {
+            if layouts.contains(&layout) {
+                return Err(self.include_error(
+                    self.tag_pos,
+                    format!(
+                        "layout inheritance cycle: {} -> {}",
+                        layouts.join(" -> "),
+                        layout
+                    ),
+                ));
+            }
+
+            let layout_pos = self.tag_pos;
+            self.read_named_blocks()?;
+
+            let layout_string = self
+                .resolver
+                .resolve(&template_file_name(&layout))
+                .map_err(|err| {
+                    self.include_error(
+                        layout_pos,
+                        format!("failed to read layout `{}`: {}", layout, err),
+                    )
+                })?;
+
+            self.in_bytes = layout_string.into_bytes();
+            self.pos = 0;
+            self.delimiters = self.default_delimiters.clone();
+            self.add_source(Some(template_file_name(&layout)));
+            self.read_delimiters()?;
+
+            layouts.push(layout);
+        }
+
+        Ok(())
+    }
+
+    fn read_layout_name(&mut self) -> TemplateResult<Option<String>> {
+        let start = self.pos;
+        self.skip_whitespace();
+
+        if self.peek_tag() != Some(LAYOUT_OPEN) {
+            self.pos = start;
+            return Ok(None);
+        }
+
+        self.tag_pos = self.pos;
+        self.skip_tag_open();
+        let name = self.skip_until_close(LAYOUT_CLOSE)?;
+        self.skip_tag_close(LAYOUT_CLOSE);
+
+        Ok(Some(trim_tag_name(&name)))
+    }
+
+    /// Collects top level named blocks, everything else is ignored,
+    /// blocks of extending templates take precedence
+    fn read_named_blocks(&mut self) -> TemplateResult<()> {
+        while self.pos < self.in_bytes.len() {
+            if self.skip_verbatim()? {
+                continue;
+            }
+
+            if self.peek_tag() != Some(NAMED_BLOCK_OPEN) {
+                self.skip(1);
+                continue;
+            }
+
+            let start = self.pos;
+            self.tag_pos = start;
+            self.skip_tag_open();
+            let name = trim_tag_name(&self.read_tag(NAMED_BLOCK_CLOSE)?);
+
+            self.overrides.entry(name).or_insert(BlockOverride {
+                source: self.source,
+                start,
+                pos: self.pos,
+                delimiters: self.delimiters.clone(),
+            });
+
+            self.skip_block(start)?;
+            self.skip_marker();
+        }
+
+        Ok(())
+    }
+
+    fn compile(mut self, undefined: UndefinedMode) -> TemplateResult<Template> {
+        let nodes = self.parse_html()?;
+
+        Ok(Template {
+            nodes,
+            sources: self.sources,
+            undefined,
+        })
+    }
+
+    /// Keeps current template for error locations of nodes compiled from it
+    fn add_source(&mut self, name: Option<String>) {
+        self.source = self.sources.len();
+        self.sources.push(Source {
+            name,
+            bytes: self.in_bytes.clone(),
+        });
+    }
+
+    fn parse_html(&mut self) -> TemplateResult<Vec<Node>> {
+        let mut nodes = vec![];
+
+        while self.pos < self.in_bytes.len() {
+            if self.blocks > 0 && (self.is_at_block_end() || self.is_at_else()) {
+                // if we are inside the block scope,
+                // stop and give control back to previous parser
+                // it will take care of the closing characters
+                if self.is_at_marker(BLOCK_END_TRIM) || self.is_at_marker(OPTIONAL_ELSE_TRIM) {
+                    trim_text_end(&mut nodes);
+                }
+
+                break;
+            }
+
+            self.tag_pos = self.pos;
+
+            if let Some(len) = self.escape_len() {
+                // drop the escape and output what follows as is
+                push_text(&mut nodes, &self.in_bytes[self.pos + 1..self.pos + len]);
+                self.skip(len);
+                continue;
+            }
+
+            if self.is_at_marker(RAW_OPEN) {
+                let content = self.skip_raw()?;
+                push_text(&mut nodes, &self.in_bytes[content]);
+                continue;
+            }
+
+            match self.peek_tag() {
+                Some(VARIABLE_OPEN) => {
+                    self.skip_tag_open();
 
-        let variables = ron::parse(variables_string)?;
+                    let raw = self.peek() == Some(RAW_VARIABLE_OPEN);
+                    if raw {
+                        self.skip(1);
+                    }
+
+                    self.parse_open_trim_marker(&mut nodes);
+                    nodes.push(self.parse_variable(raw)?);
+                }
+                Some(LOOP_OPEN) => {
+                    self.skip_tag_open();
+                    self.parse_open_trim_marker(&mut nodes);
+                    nodes.push(self.parse_loop()?);
+                }
+                Some(OPTIONAL_OPEN) => {
+                    self.skip_tag_open();
+                    self.parse_open_trim_marker(&mut nodes);
+                    nodes.push(self.parse_optional(OPTIONAL_CLOSE)?);
+                }
+                Some(NEGATED_OPEN) => {
+                    self.skip_tag_open();
+                    self.parse_open_trim_marker(&mut nodes);
+                    nodes.push(self.parse_optional(NEGATED_CLOSE)?);
+                }
+                Some(INLINE_OPEN) => {
+                    self.skip_tag_open();
+                    self.parse_open_trim_marker(&mut nodes);
+                    nodes.push(self.parse_inline()?);
+                }
+                Some(NAMED_BLOCK_OPEN) => {
+                    self.skip_tag_open();
+                    self.parse_open_trim_marker(&mut nodes);
+                    nodes.extend(self.parse_named_block()?);
+                }
+                Some(COMMENT_OPEN) => {
+                    self.skip_tag_open();
+                    self.parse_open_trim_marker(&mut nodes);
+                    self.skip_comment()?;
+                }
+                // not a tag, or a block end outside of the block scope
+                _ => {
+                    push_text(&mut nodes, &self.in_bytes[self.pos..self.pos + 1]);
+                    self.skip(1);
+                }
+            }
+        }
+
+        Ok(nodes)
+    }
+
+    fn parse_variable(&mut self, raw: bool) -> TemplateResult<Node> {
+        let start = self.tag_pos;
+        let sigils = if raw {
+            RAW_VARIABLE_CLOSE
+        } else {
+            VARIABLE_CLOSE
+        };
+        let variable_string = self.read_tag(sigils)?;
+
+        Ok(Node::Var {
+            span: self.span(start),
+            variable: self.parse_variable_string(start, &variable_string)?,
+            raw,
+        })
+    }
+
+    fn parse_inline(&mut self) -> TemplateResult<Node> {
+        let start = self.tag_pos;
+        let name = self.read_tag(INLINE_CLOSE)?;
+
+        let nodes = match name.strip_prefix(PARTIAL_PREFIX) {
+            Some(partial) => self.parse_partial(start, partial.trim())?,
+            None => match name.as_str() {
+                "css" => {
+                    let css_string = self.resolve_asset(start, CSS_NAME)?;
+
+                    vec![Node::Text(
+                        format!("<style>\n{}</style>", css_string).into_bytes(),
+                    )]
+                }
+                "js" => {
+                    let js_string = self.resolve_asset(start, JS_NAME)?;
+
+                    vec![Node::Text(
+                        format!("<script>\n{}</script>", js_string).into_bytes(),
+                    )]
+                }
+                _ => {
+                    return Err(
+                        self.include_error(start, format!("unexpected inline asset `{}`", name))
+                    )
+                }
+            },
+        };
+
+        Ok(Node::Inline(nodes))
+    }
+
+    /// Compiles another template to render in place,
+    /// using current scopes
+    fn parse_partial(&mut self, start: usize, name: &str) -> TemplateResult<Vec<Node>> {
+        if self.partials.iter().any(|partial| partial == name) {
+            return Err(self.include_error(
+                start,
+                format!(
+                    "partial include cycle: {} -> {}",
+                    self.partials.join(" -> "),
+                    name
+                ),
+            ));
+        }
+
+        let partial = self
+            .resolver
+            .resolve(&template_file_name(name))
+            .map_err(|err| {
+                self.include_error(start, format!("failed to read partial `{}`: {}", name, err))
+            })?;
+
+        let in_bytes = std::mem::replace(&mut self.in_bytes, partial.into_bytes());
+        let pos = std::mem::replace(&mut self.pos, 0);
+        let blocks = std::mem::replace(&mut self.blocks, 0);
+        let delimiters = std::mem::replace(&mut self.delimiters, self.default_delimiters.clone());
+        let source = self.source;
+        self.partials.push(name.to_string());
+        self.add_source(Some(template_file_name(name)));
+
+        self.read_delimiters()?;
+        let nodes = self.parse_html()?;
+
+        self.partials.pop();
+        self.in_bytes = in_bytes;
+        self.pos = pos;
+        self.blocks = blocks;
+        self.delimiters = delimiters;
+        self.source = source;
+
+        Ok(nodes)
+    }
+
+    fn resolve_asset(&self, start: usize, name: &str) -> TemplateResult<String> {
+        self.resolver
+            .resolve(name)
+            .map_err(|err| self.include_error(start, format!("failed to read `{}`: {}", name, err)))
+    }
+
+    fn parse_loop(&mut self) -> TemplateResult<Node> {
+        let start = self.tag_pos;
+        let variable_string = self.read_tag(LOOP_CLOSE)?;
+        let span = self.span(start);
+
+        // `as` is the second to last word, or the last one if the name is missing
+        let words = variable_string.split_whitespace().collect::<Vec<_>>();
+        let (variable_string, item_name) = match words[..] {
+            [.., LOOP_ALIAS_KEYWORD] => {
+                return Err(self.syntax_error(
+                    start,
+                    format!("expected loop variable name after `{}`", LOOP_ALIAS_KEYWORD),
+                ));
+            }
+            [.., LOOP_ALIAS_KEYWORD, alias] => {
+                if !alias
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+                {
+                    return Err(self.syntax_error(
+                        start,
+                        format!("unexpected loop variable name `{}`", alias),
+                    ));
+                }
+
+                let variable_string = variable_string
+                    .trim_end()
+                    .strip_suffix(alias)
+                    .and_then(|rest| rest.trim_end().strip_suffix(LOOP_ALIAS_KEYWORD))
+                    .unwrap_or_default()
+                    .trim_end();
+
+                (variable_string.to_string(), alias.to_string())
+            }
+            _ => (variable_string.clone(), LOOP_ITEM_VARIABLE.to_string()),
+        };
+
+        let variable = self.parse_variable_string(start, &variable_string)?;
+        let body = self.parse_branch(start)?;
+
+        if self.is_at_else() {
+            return Err(self.syntax_error(self.pos, "unexpected else branch in loop block"));
+        }
+
+        self.skip_marker();
+
+        Ok(Node::Loop {
+            span,
+            variable,
+            item_name,
+            body,
+        })
+    }
+
+    /// Compiles Optional block, or Negated one (closed by `!` sigil) with its branches swapped
+    fn parse_optional(&mut self, sigils: &[u8]) -> TemplateResult<Node> {
+        let start = self.tag_pos;
+        let condition_string = self.read_tag(sigils)?;
+        let span = self.span(start);
+
+        let condition = self.parse_condition(start, &condition_string)?;
+        let mut then = self.parse_branch(start)?;
+        let mut otherwise = vec![];
+
+        if self.is_at_else() {
+            self.skip_marker();
+            otherwise = self.parse_branch(start)?;
+
+            if self.is_at_else() {
+                return Err(self.syntax_error(self.pos, "unexpected second else branch"));
+            }
+        }
+
+        self.skip_marker();
+
+        if sigils == NEGATED_CLOSE {
+            std::mem::swap(&mut then, &mut otherwise);
+        }
+
+        Ok(Node::Optional {
+            span,
+            condition,
+            then,
+            otherwise,
+        })
+    }
+
+    /// Parses Optional block condition, e.g. `{? $it.count > 2 and not $last ?}`
+    fn parse_condition(&self, start: usize, condition: &str) -> TemplateResult<Expr> {
+        Expr::from_condition(condition).map_err(|err| {
+            self.syntax_error(start, format!("invalid condition `{}`: {}", condition, err))
+        })
+    }
+
+    /// Named block is its body, or the body of the block overriding it in extending template,
+    /// trim markers of the layout block apply to the overriding body too
+    fn parse_named_block(&mut self) -> TemplateResult<Vec<Node>> {
+        let start = self.tag_pos;
+        let name = self.skip_until_close(NAMED_BLOCK_CLOSE)?;
+        self.skip_tag_close(NAMED_BLOCK_CLOSE);
+        let is_start_trimmed = name.ends_with(TRIM_MARKER as char);
+        let name = trim_tag_name(&self.trim_tag_end(name));
+
+        // the override is taken out while compiled, so its own block of the same name
+        // gets the default body
+        let body = match self.overrides.remove(&name) {
+            Some(block) => {
+                let mut body = self.parse_block_override(&name, &block)?;
+                self.overrides.insert(name, block);
+                self.skip_block(start)?;
+
+                if is_start_trimmed {
+                    trim_text_start(&mut body);
+                }
+                if self.is_at_marker(BLOCK_END_TRIM) {
+                    trim_text_end(&mut body);
+                }
+
+                body
+            }
+            None => {
+                let body = self.parse_branch(start)?;
+                self.expect_named_block_end(&name)?;
+                body
+            }
+        };
+
+        self.skip_marker();
+
+        Ok(body)
+    }
+
+    /// Compiles overriding block body in its own template,
+    /// so nodes refer to the right source
+    fn parse_block_override(
+        &mut self,
+        name: &str,
+        block: &BlockOverride,
+    ) -> TemplateResult<Vec<Node>> {
+        let in_bytes =
+            std::mem::replace(&mut self.in_bytes, self.sources[block.source].bytes.clone());
+        let pos = std::mem::replace(&mut self.pos, block.pos);
+        let blocks = std::mem::replace(&mut self.blocks, 0);
+        let delimiters = std::mem::replace(&mut self.delimiters, block.delimiters.clone());
+        let source = std::mem::replace(&mut self.source, block.source);
+
+        let body = self.parse_branch(block.start)?;
+        self.expect_named_block_end(name)?;
+
+        self.in_bytes = in_bytes;
+        self.pos = pos;
+        self.blocks = blocks;
+        self.delimiters = delimiters;
+        self.source = source;
+
+        Ok(body)
+    }
+
+    fn expect_named_block_end(&self, name: &str) -> TemplateResult<()> {
+        if self.is_at_else() {
+            return Err(self.syntax_error(
+                self.pos,
+                format!("unexpected else branch in `{}` named block", name),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn parse_branch(&mut self, start: usize) -> TemplateResult<Vec<Node>> {
+        self.blocks += 1;
+        let nodes = self.parse_html()?;
+        self.blocks -= 1;
+
+        self.expect_block_end(start)?;
+
+        Ok(nodes)
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span {
+            source: self.source,
+            start,
+            end: self.pos,
+        }
+    }
+
+    /// Checks that block started at `start` is followed by its end or else marker
+    fn expect_block_end(&self, start: usize) -> TemplateResult<()> {
+        if self.is_at_block_end() || self.is_at_else() {
+            Ok(())
+        } else {
+            Err(self.unterminated(start, self.marker_string(BLOCK_END)))
+        }
+    }
+
+    /// Skips block body until its matching block end, including else branches
+    fn skip_block(&mut self, start: usize) -> TemplateResult<()> {
+        while self.skip_branch(start)? {
+            self.skip_marker();
+        }
+
+        Ok(())
+    }
+
+    /// Skips Optional block branch until its matching block end or else marker,
+    /// returns `true` if stopped at the else marker
+    fn skip_branch(&mut self, start: usize) -> TemplateResult<bool> {
+        let mut inner_blocks = 0;
+
+        loop {
+            if inner_blocks == 0 && self.is_at_else() {
+                return Ok(true);
+            }
+
+            if self.is_at_block_end() {
+                if inner_blocks == 0 {
+                    return Ok(false);
+                }
+
+                inner_blocks -= 1;
+                self.skip_marker();
+                continue;
+            }
+
+            if self.pos >= self.in_bytes.len() {
+                return Err(self.unterminated(start, self.marker_string(BLOCK_END)));
+            }
+
+            if self.skip_verbatim()? {
+                continue;
+            }
+
+            match self.peek_tag() {
+                Some(COMMENT_OPEN) => {
+                    let tag_pos = std::mem::replace(&mut self.tag_pos, self.pos);
+                    self.skip_tag_open();
+                    self.skip_comment()?;
+                    self.tag_pos = tag_pos;
+                }
+                Some(sigil) if BLOCK_OPENING_SIGILS.contains(&sigil) => {
+                    self.skip_tag_open();
+                    inner_blocks += 1;
+                }
+                _ => self.skip(1),
+            }
+        }
+    }
+
+    /// Returns length of escape sequence at current position, either `\`
+    /// with opening delimiter and the byte following it, or `\\` before opening delimiter,
+    /// only if the delimiter starts a tag or a marker
+    fn escape_len(&self) -> Option<usize> {
+        let rest = self.in_bytes.get(self.pos..).unwrap_or_default();
+
+        match rest {
+            [ESCAPE, ..] if self.is_tag_at(self.pos + 1) => Some(2 + self.delimiters.open.len()),
+            [ESCAPE, ESCAPE, ..] if self.is_tag_at(self.pos + 2) => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Checks that a tag (e.g. `{{`) or a marker (e.g. `{:}`) starts at the position
+    fn is_tag_at(&self, pos: usize) -> bool {
+        let Delimiters { open, close } = &self.delimiters;
+        let rest = match self.in_bytes.get(pos..) {
+            Some(rest) if rest.starts_with(open) => &rest[open.len()..],
+            _ => return false,
+        };
+
+        matches!(rest.first(), Some(sigil) if TAG_SIGILS.contains(sigil))
+            || MARKERS
+                .iter()
+                .any(|marker| rest.starts_with(marker) && rest[marker.len()..].starts_with(close))
+    }
+
+    /// Skips escape sequence or `{raw}` block outside of rendering,
+    /// returns `true` if anything was skipped
+    fn skip_verbatim(&mut self) -> TemplateResult<bool> {
+        if let Some(len) = self.escape_len() {
+            self.skip(len);
+            return Ok(true);
+        }
+
+        if self.is_at_marker(RAW_OPEN) {
+            self.skip_raw()?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Skips `{raw} ... {/raw}` block, returns range of its contents
+    fn skip_raw(&mut self) -> TemplateResult<Range<usize>> {
+        let tag_pos = self.pos;
+        self.skip(self.marker_len(RAW_OPEN));
+        let start = self.pos;
+
+        while !self.is_at_marker(RAW_CLOSE) {
+            if self.pos >= self.in_bytes.len() {
+                return Err(self.unterminated(tag_pos, self.marker_string(RAW_CLOSE)));
+            }
+
+            self.skip(1);
+        }
+
+        let end = self.pos;
+        self.skip(self.marker_len(RAW_CLOSE));
+
+        Ok(start..end)
+    }
+
+    fn skip_comment(&mut self) -> TemplateResult<()> {
+        self.read_tag(COMMENT_CLOSE)?;
+
+        Ok(())
+    }
+
+    fn is_at_block_end(&self) -> bool {
+        self.is_at_marker(BLOCK_END) || self.is_at_marker(BLOCK_END_TRIM)
+    }
+
+    fn is_at_else(&self) -> bool {
+        self.is_at_marker(OPTIONAL_ELSE) || self.is_at_marker(OPTIONAL_ELSE_TRIM)
+    }
+
+    /// Checks for marker between delimiters, e.g. `{:}`
+    fn is_at_marker(&self, marker: &[u8]) -> bool {
+        let Delimiters { open, close } = &self.delimiters;
+        let rest = self.in_bytes.get(self.pos..).unwrap_or_default();
+
+        rest.len() >= open.len() + marker.len() + close.len()
+            && rest.starts_with(open)
+            && rest[open.len()..].starts_with(marker)
+            && rest[open.len() + marker.len()..].starts_with(close)
+    }
+
+    /// Skips block end or else marker,
+    /// `{-}` and `{-:}` also skip the whitespace following them
+    fn skip_marker(&mut self) {
+        for marker in [BLOCK_END_TRIM, OPTIONAL_ELSE_TRIM, OPTIONAL_ELSE, BLOCK_END] {
+            if self.is_at_marker(marker) {
+                self.skip(self.marker_len(marker));
+
+                if marker.first() == Some(&TRIM_MARKER) {
+                    self.skip_whitespace();
+                }
+
+                return;
+            }
+        }
+    }
+
+    fn marker_len(&self, marker: &[u8]) -> usize {
+        self.delimiters.open.len() + marker.len() + self.delimiters.close.len()
+    }
+
+    fn marker_string(&self, marker: &[u8]) -> String {
+        let Delimiters { open, close } = &self.delimiters;
+
+        String::from_utf8_lossy(&[&open[..], marker, &close[..]].concat()).to_string()
+    }
+
+    /// Handles `-` right after tag opening pair, e.g. `{{- name }}`,
+    /// trimming template whitespace before the tag
+    fn parse_open_trim_marker(&mut self, nodes: &mut Vec<Node>) {
+        if self.peek() == Some(TRIM_MARKER) {
+            self.skip(1);
+            trim_text_end(nodes);
+        }
+    }
+
+    /// Reads tag contents and skips its closing sigils and delimiter,
+    /// handling `-` before them, e.g. `{{ name -}}`
+    fn read_tag(&mut self, sigils: &[u8]) -> TemplateResult<String> {
+        let name = self.skip_until_close(sigils)?;
+        self.skip_tag_close(sigils);
+
+        Ok(self.trim_tag_end(name))
+    }
+
+    /// Strips `-` closing trim marker from tag contents,
+    /// skipping whitespace following the tag
+    fn trim_tag_end(&mut self, name: String) -> String {
+        match name.strip_suffix(TRIM_MARKER as char) {
+            Some(name) => {
+                self.skip_whitespace();
+                name.trim_end().to_string()
+            }
+            None => name,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(byte) if byte.is_ascii_whitespace()) {
+            self.skip(1);
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.in_bytes.get(self.pos).copied()
+    }
+
+    /// Returns opening sigil if at tag opening delimiter
+    fn peek_tag(&self) -> Option<u8> {
+        if self.is_at(&self.delimiters.open) {
+            self.in_bytes
+                .get(self.pos + self.delimiters.open.len())
+                .copied()
+        } else {
+            None
+        }
+    }
+
+    fn skip_tag_open(&mut self) {
+        self.skip(self.delimiters.open.len() + 1);
+    }
+
+    fn skip_tag_close(&mut self, sigils: &[u8]) {
+        self.skip(sigils.len() + self.delimiters.close.len());
+    }
+
+    fn is_at(&self, seq: &[u8]) -> bool {
+        self.in_bytes
+            .get(self.pos..)
+            .unwrap_or_default()
+            .starts_with(seq)
+    }
+
+    fn skip(&mut self, n: usize) {
+        self.pos += n;
+    }
+
+    /// Reads tag contents until the closing sigils and delimiter
+    /// of the tag started at `tag_pos`
+    fn skip_until_close(&mut self, sigils: &[u8]) -> TemplateResult<String> {
+        let start = self.pos;
+
+        while !(self.is_at(sigils)
+            && self.in_bytes[self.pos + sigils.len()..].starts_with(&self.delimiters.close))
+        {
+            if self.pos >= self.in_bytes.len() {
+                let expected = [sigils, &self.delimiters.close[..]].concat();
+
+                return Err(
+                    self.unterminated(self.tag_pos, String::from_utf8_lossy(&expected).to_string())
+                );
+            }
+
+            self.skip(1);
+        }
+
+        let name = String::from_utf8_lossy(&self.in_bytes[start..self.pos]);
+
+        Ok(name.trim().to_string())
+    }
+
+    fn parse_variable_string(&self, start: usize, var_str: &str) -> TemplateResult<Variable> {
+        let (name, pipes) = match &var_str.split(PIPE_SEPARATOR).collect::<Vec<&str>>()[..] {
+            [name] => (name.to_string(), vec![]),
+            [name, pipes @ ..] => (
+                name.trim().to_string(),
+                pipes
+                    .iter()
+                    .map(|x| self.parse_pipe(start, x.trim()))
+                    .collect::<TemplateResult<Vec<_>>>()?,
+            ),
+            [] => {
+                return Err(
+                    self.syntax_error(start, format!("unexpected variable string {:?}", var_str))
+                )
+            }
+        };
+
+        Ok(Variable { name, pipes })
+    }
+
+    fn parse_pipe(&self, start: usize, pipe_str: &str) -> TemplateResult<Box<dyn Pipe>> {
+        let name = pipe_str.split(' ').next().unwrap_or_default();
+
+        if !pipe::is_known(name) {
+            return Err(TemplateError::UnknownPipe {
+                name: name.to_string(),
+                location: self.location(start),
+            });
+        }
+
+        pipe::parse(pipe_str).map_err(|err| self.syntax_error(start, err.to_string()))
+    }
+
+    fn location(&self, offset: usize) -> Location {
+        let template = self.sources[self.source].name.clone();
+
+        Location::from_source(template, &self.in_bytes, offset)
+    }
+
+    fn unterminated(&self, start: usize, expected: String) -> TemplateError {
+        TemplateError::UnterminatedTag {
+            expected,
+            location: self.location(start),
+        }
+    }
+
+    fn syntax_error(&self, start: usize, message: impl Into<String>) -> TemplateError {
+        TemplateError::Syntax {
+            message: message.into(),
+            location: self.location(start),
+        }
+    }
+
+    fn include_error(&self, start: usize, message: impl Into<String>) -> TemplateError {
+        TemplateError::Include {
+            message: message.into(),
+            location: self.location(start),
+        }
+    }
+}
+
+/// Appends text to the previous node if it's text too
+fn push_text(nodes: &mut Vec<Node>, bytes: &[u8]) {
+    match nodes.last_mut() {
+        Some(Node::Text(text)) => text.extend_from_slice(bytes),
+        _ => nodes.push(Node::Text(bytes.to_vec())),
+    }
+}
+
+/// Trims trailing whitespace of the last node if it's text,
+/// or of the last nodes of optional branches and partials, which are rendered right before,
+/// rendered values are never trimmed
+fn trim_text_end(nodes: &mut Vec<Node>) {
+    match nodes.last_mut() {
+        Some(Node::Text(text)) => {
+            let end = text
+                .iter()
+                .rposition(|byte| !byte.is_ascii_whitespace())
+                .map_or(0, |pos| pos + 1);
+            text.truncate(end);
+
+            // whitespace only text is dropped, trimming the node before it too
+            if text.is_empty() {
+                nodes.pop();
+                trim_text_end(nodes);
+            }
+        }
+        Some(Node::Optional {
+            then, otherwise, ..
+        }) => {
+            trim_text_end(then);
+            trim_text_end(otherwise);
+        }
+        Some(Node::Inline(nodes)) => trim_text_end(nodes),
+        _ => (),
+    }
+}
+
+/// Trims leading whitespace of the first node if it's text
+fn trim_text_start(nodes: &mut Vec<Node>) {
+    if let Some(Node::Text(text)) = nodes.first_mut() {
+        let start = text
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .unwrap_or(text.len());
+        text.drain(..start);
+
+        if text.is_empty() {
+            nodes.remove(0);
+        }
+    }
+}
+
+/// Strips whitespace and trim markers from names of tags
+/// read outside of rendering, e.g. `{+- title -+}`
+fn trim_tag_name(name: &str) -> String {
+    name.trim_matches(|ch: char| ch == TRIM_MARKER as char || ch.is_ascii_whitespace())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(files: &[(&str, &str)]) -> HashMap<String, String> {
+        files
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parser_should_handle_template_variable() {
+        let template = "\
+<div>{{ name }}</div>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<div>Test name</div>");
+    }
+
+    #[test]
+    fn parser_should_handle_template_variable_with_reverse_pipe() {
+        let template = "\
+<div>{{ name | $reverse }}</div>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: 12345
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<div>54321</div>");
+    }
+
+    #[test]
+    fn parser_should_handle_template_variable_with_two_reverse_pipes() {
+        let template = "\
+<div>{{ name | $reverse | $reverse }}</div>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: 12345
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<div>12345</div>");
+    }
+
+    #[test]
+    fn parser_should_handle_template_loop_with_reverse_pipe() {
+        let template = "\
+{* items | $reverse *}<div>{{ $it.name }}</div>{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        {
+            name: One
+        }
+        {
+            name: Two
+        }
+        {
+            name: Three
+        }
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<div>Three</div><div>Two</div><div>One</div>");
+    }
+
+    #[test]
+    fn parser_should_escape_html_in_template_variable() {
+        let template = "\
+<a title=\"{{ title }}\">{{ name }}</a>\
+"
+        .to_string();
+
+        let variables = r#"
+{
+    title: Say "hi" & 'bye'
+    name: <b>Test</b>
+}
+"#
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(
+            result,
+            "<a title=\"Say &quot;hi&quot; &amp; &#39;bye&#39;\">&lt;b&gt;Test&lt;/b&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn parser_should_handle_raw_template_variable() {
+        let template = "\
+<div>{{{ name }}}</div><div>{{{ name | $reverse }}}</div>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: <b>&nbsp;</b>
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<div><b>&nbsp;</b></div><div>>b/<;psbn&>b<</div>");
+    }
+
+    #[test]
+    fn parser_should_fail_on_unterminated_raw_template_variable() {
+        let template = "\
+<div>{{{ name }}</div>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+}
+"
+        .to_string();
+
+        assert!(render_str(&template, &ron::parse(variables).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parser_should_handle_optional_block() {
+        let template = "\
+{? name ?}<div>{{ name }}</div>{}{? missing ?}<div>{{ missing }}</div>{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<div>Test name</div>");
+    }
+
+    #[test]
+    fn parser_should_handle_optional_block_else_branch() {
+        let template = "\
+{* items *}\
+<div>{? $it.url ?}<a href=\"{{ $it.url }}\">{{ $it.name }}</a>{:}{{ $it.name }}{}</div>\
+{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        {
+            name: One
+            url: one.html
+        }
+        {
+            name: Two
+        }
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(
+            result,
+            "<div><a href=\"one.html\">One</a></div><div>Two</div>"
+        );
+    }
+
+    #[test]
+    fn parser_should_handle_nested_blocks_in_optional_block_branches() {
+        let template = "\
+{? missing ?}{* items *}{? $it ?}{{ $it }}{:}-{}{}{:}{* items *}[{? $it ?}{{ $it }}{:}-{}]{}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        One
+        Two
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "[One][Two]");
+    }
+
+    #[test]
+    fn parser_should_fail_on_else_branch_in_loop_block() {
+        let template = "\
+{* items *}{{ $it }}{:}-{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        One
+    ]
+}
+"
+        .to_string();
+
+        assert!(render_str(&template, &ron::parse(variables).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parser_should_handle_negated_block() {
+        let template = "\
+{* items *}\
+<div>{{ $it.name }}{! $it.url !} (no link){}{! $it.formerly !}{:} (formerly){}</div>\
+{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        {
+            name: One
+            url: one.html
+            formerly: []
+        }
+        {
+            name: Two
+            formerly: [
+                Three
+            ]
+        }
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<div>One</div><div>Two (no link) (formerly)</div>");
+    }
+
+    #[test]
+    fn parser_should_skip_nested_negated_block() {
+        let template = "\
+{? missing ?}{! name !}-{}{{ name }}{:}+{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "+");
+    }
+
+    #[test]
+    fn parser_should_handle_nested_partials() {
+        let template = "\
+{> partial:header <}{* items *}{> partial:item <}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+    items: [
+        One
+        Two
+    ]
+}
+"
+        .to_string();
+
+        let resolver = files(&[
+            ("header.rustache", "<h1>{{ name }}</h1>"),
+            ("item.rustache", "<div>{> partial:link <}</div>"),
+            ("link.rustache", "{{ $it }}"),
+        ]);
+
+        let result = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
+
+        assert_eq!(result, "<h1>Test name</h1><div>One</div><div>Two</div>");
+    }
+
+    #[test]
+    fn parser_should_fail_on_partials_cycle() {
+        let template = "\
+{> partial:header <}\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        let resolver = files(&[
+            ("header.rustache", "{> partial:title <}"),
+            ("title.rustache", "{> partial:header <}"),
+        ]);
+
+        let error = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap_err();
+
+        assert_eq!(
+            error.message(),
+            "partial include cycle: header -> title -> header"
+        );
+        assert_eq!(error.location().template.as_deref(), Some("title.rustache"));
+    }
+
+    #[test]
+    fn parser_should_handle_layouts() {
+        let template = "
+{^ page ^}
+
+{+ title +}{{ name }}{}
+
+{+ content +}{* items *}<p>{{ $it }}</p>{}{}
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+    items: [
+        One
+        Two
+    ]
+}
+"
+        .to_string();
+
+        let resolver = files(&[
+            (
+                "page.rustache",
+                "\
+{^ layout ^}\
+{+ head +}{}\
+{+ body +}<main>{+ content +}{}</main>{}\
+",
+            ),
+            (
+                "layout.rustache",
+                "\
+<title>{+ title +}Default title{}</title>\
+{+ head +}<meta name=\"author\" content=\"{{ name }}\">{}\
+<body>{+ body +}{}</body>\
+",
+            ),
+        ]);
+
+        let result = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            result,
+            "\
+<title>Test name</title>\
+<body><main><p>One</p><p>Two</p></main></body>\
+"
+        );
+    }
+
+    #[test]
+    fn parser_should_compile_layout_blocks_with_their_own_delimiters() {
+        let template = "\
+{^ layout ^}{+ title +}{{ name }}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Name
+}
+"
+        .to_string();
+
+        let resolver = files(&[(
+            "layout.rustache",
+            "{= <% %> =}<h1><%+ title +%><%%></h1><%{ name }%> {{ name }}",
+        )]);
+
+        let result = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
+
+        assert_eq!(result, "<h1>Name</h1>Name {{ name }}");
+    }
+
+    #[test]
+    fn parser_should_report_error_locations_in_layouts() {
+        let resolver = files(&[(
+            "layout.rustache",
+            "<html>\n<title>{+ title +}{}</title>\n<body>\n{+ body +}{}\n{{ oops }}\n</body>",
+        )]);
+        let renderer = Renderer::new().name("page.rustache").resolver(resolver);
+        let variables = ron::parse("{\n    name: Name\n}".to_string()).unwrap();
+
+        let page = "{^ layout ^}\n\n{+ title +}{{ name }}{}\n\n{+ body +}\n{{ nam }}\n{}";
+        let error = renderer.render(page, &variables).unwrap_err();
+
+        assert_eq!(error.location().template.as_deref(), Some("page.rustache"));
+        assert_eq!(error.location().line, 6);
+        assert_eq!(error.location().source_line, "{{ nam }}");
+
+        let page = "{^ layout ^}\n{+ title +}{{ name }}{}\n{+ body +}{{ name }}{}";
+        let error = renderer.render(page, &variables).unwrap_err();
+
+        assert_eq!(
+            error.location().template.as_deref(),
+            Some("layout.rustache")
+        );
+        assert_eq!(error.location().line, 5);
+        assert_eq!(error.location().source_line, "{{ oops }}");
+    }
+
+    #[test]
+    fn parser_should_fail_on_layouts_cycle() {
+        let template = "\
+{^ page ^}\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        let resolver = files(&[
+            ("page.rustache", "{^ layout ^}"),
+            ("layout.rustache", "{^ page ^}"),
+        ]);
+
+        let error = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap_err();
+
+        assert_eq!(
+            error.message(),
+            "layout inheritance cycle: page -> layout -> page"
+        );
+    }
+
+    #[test]
+    fn parser_should_fail_on_inline_asset_without_resolver() {
+        let template = "\
+{> css <}\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        assert!(render_str(&template, &ron::parse(variables).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parser_should_handle_loop_metadata_variables() {
+        let template = "\
+{* items *}\
+{? $first ?}[{}{{ $index }}/{{ $index1 }}/{{ $length }}:{{ $it }}{! $last !}, {:}]{}\
+{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        One
+        Two
+        Three
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "[0/1/3:One, 1/2/3:Two, 2/3/3:Three]");
+    }
+
+    #[test]
+    fn parser_should_handle_empty_loop() {
+        let template = "\
+<ul>{* items *}<li>{{ $it }}</li>{}</ul>\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: []
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<ul></ul>");
+    }
+
+    #[test]
+    fn parser_should_handle_named_loop_variables() {
+        let template = "\
+{* jobs | $reverse as job *}\
+<div>{{ job.name }}{* job.formerly as former *}, {{ former.name }} before {{ job.name }}{}</div>\
+{* job.formerly *}[{{ $it.name }}]{}\
+{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    jobs: [
+        {
+            name: One
+            formerly: [
+                {
+                    name: Two
+                }
+                {
+                    name: Three
+                }
+            ]
+        }
+        {
+            name: Four
+            formerly: []
+        }
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(
+            result,
+            "<div>Four</div><div>One, Two before One, Three before One</div>[Two][Three]"
+        );
+    }
+
+    #[test]
+    fn parser_should_fail_on_invalid_loop_variable_name() {
+        let template = "\
+{* items as $it.name *}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: []
+}
+"
+        .to_string();
+
+        assert!(render_str(&template, &ron::parse(variables).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parser_should_fail_on_missing_loop_variable_name() {
+        let template = "\
+{* items as *}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: []
+}
+"
+        .to_string();
+
+        assert!(matches!(
+            render_str(&template, &ron::parse(variables).unwrap()),
+            Err(TemplateError::Syntax { message, .. }) if message == "expected loop variable name after `as`"
+        ));
+    }
+
+    #[test]
+    fn parser_should_look_up_root_variables_inside_loops() {
+        let template = "\
+{* items *}<div>{{ name }}: {{ $it.name }}{? $it.url ?}{:} ({{ url }}){}</div>{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+    url: default.html
+    items: [
+        {
+            name: One
+            url: one.html
+        }
+        {
+            name: Two
+        }
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(
+            result,
+            "<div>Test name: One</div><div>Test name: Two (default.html)</div>"
+        );
+    }
+
+    #[test]
+    fn parser_should_try_full_variable_path_against_each_scope() {
+        let template = "\
+{* items *}{* $it.children *}[{{ $it.name }}/{{ title }}/{{ $parent.$it.title }}]{}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    title: Root
+    items: [
+        {
+            title: Outer
+            children: [
+                {
+                    name: Inner
+                }
+            ]
+        }
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "[Inner/Root/Outer]");
+    }
+
+    #[test]
+    fn parser_should_not_fall_back_to_outer_scope_for_missing_property() {
+        let template = "\
+{* items *}{* $it.formerly *}[{{ $it.name }} {? $it.url ?}{{ $it.url }}{:}no link{}]{}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        {
+            name: Outer
+            url: outer.html
+            formerly: [
+                {
+                    name: Inner
+                }
+            ]
+        }
+    ]
+}
+"
+        .to_string();
+
+        let variables = ron::parse(variables).unwrap();
+        let result = render_str(&template, &variables).unwrap();
+
+        assert_eq!(result, "[Inner no link]");
+
+        let template = "{* items *}{* $it.formerly *}{{ $it.url }}{}{}".to_string();
+
+        assert!(matches!(
+            render_str(&template, &variables),
+            Err(TemplateError::UndefinedVariable { .. })
+        ));
+    }
+
+    #[test]
+    fn parser_should_handle_root_and_parent_scope_prefixes() {
+        let template = "\
+{* items *}\
+{* $it.children *}\
+[{{ $it.name }} {{ $parent.$it.name }} {{ $parent.$index }} {{ $root.name }} {{ $parent.$parent.name }}]\
+{}\
+{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Root
+    items: [
+        {
+            name: Outer
+            children: [
+                {
+                    name: Inner
+                }
+            ]
+        }
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "[Inner Outer 0 Root Root]");
+    }
+
+    #[test]
+    fn parser_should_not_fall_back_to_inner_scopes_for_root_prefix() {
+        let template = "\
+{* items *}{{ $root.$it }}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        One
+    ]
+}
+"
+        .to_string();
+
+        let error = render_str(&template, &ron::parse(variables).unwrap()).unwrap_err();
+
+        assert!(matches!(
+            error,
+            TemplateError::UndefinedVariable { name, .. } if name == "$root.$it"
+        ));
+    }
+
+    #[test]
+    fn parser_should_fail_on_parent_prefix_in_root_scope() {
+        let template = "\
+{{ $parent.name }}\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+}
+"
+        .to_string();
+
+        assert!(render_str(&template, &ron::parse(variables).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parser_should_fail_on_unterminated_tags() {
+        let cases = [
+            ("<div>\n  {{ name </div>", "}}", 2, 3),
+            ("<div>\n  {* items *}{{ $it }}</div>", "{}", 2, 3),
+            ("{? name ?}{* items *}{}", "{}", 1, 1),
+            ("{? missing ?}{* items *}{}", "{}", 1, 1),
+            ("{! name !}{:}{{ name }}", "{}", 1, 1),
+            ("{> css", "<}", 1, 1),
+        ];
+
+        let variables = "
+{
+    name: Test name
+    items: [
+        One
+    ]
+}
+"
+        .to_string();
+        let variables = ron::parse(variables).unwrap();
+
+        for (template, expected_pair, expected_line, expected_column) in cases {
+            match render_str(template, &variables) {
+                Err(TemplateError::UnterminatedTag { expected, location }) => {
+                    assert_eq!(expected, expected_pair, "{}", template);
+                    assert_eq!(location.line, expected_line, "{}", template);
+                    assert_eq!(location.column, expected_column, "{}", template);
+                }
+                result => panic!("Unexpected result for {}: {:?}", template, result),
+            }
+        }
+    }
+
+    #[test]
+    fn parser_should_fail_on_unknown_pipe() {
+        let template = "\
+<div>{{ name | $reverse | $upper }}</div>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Test name
+}
+"
+        .to_string();
+
+        let error = render_str(&template, &ron::parse(variables).unwrap()).unwrap_err();
+
+        assert!(matches!(
+            error,
+            TemplateError::UnknownPipe { ref name, .. } if name == "$upper"
+        ));
+        assert_eq!(error.location().column, 6);
+    }
+
+    #[test]
+    fn parser_should_fail_on_type_mismatch() {
+        let variables = "
+{
+    name: Test name
+    items: []
+}
+"
+        .to_string();
+        let variables = ron::parse(variables).unwrap();
+
+        for template in ["{* name *}{}", "{{ items }}", "{{ name.first }}"] {
+            assert!(
+                matches!(
+                    render_str(template, &variables),
+                    Err(TemplateError::TypeMismatch { .. })
+                ),
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn parser_should_report_error_location_in_partial() {
+        let template = "\
+<main>\n{> partial:header <}</main>\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        let resolver = files(&[(
+            "header.rustache",
+            "<header>\n  <h1>{{ title }}</h1>\n</header>",
+        )]);
+
+        let error = Renderer::new()
+            .name("index.rustache")
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "\
+error: variable `title` is undefined
+ --> header.rustache:2:7
+  |
+2 |   <h1>{{ title }}</h1>
+  |       ^"
+        );
+    }
+
+    #[test]
+    fn parser_should_skip_comments() {
+        let template = "\
+<ul>{# items
+    {{ not_a_variable }} {* not_a_loop *}
+#}{* items *}<li>{# item #}{{ $it }}</li>{? missing ?}{# {} #}-{:}{# {? #}+{}{}</ul>{# {} #}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        One
+        Two
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<ul><li>One</li>+<li>Two</li>+</ul>");
+    }
+
+    #[test]
+    fn parser_should_fail_on_unterminated_comment() {
+        let template = "\
+<div>{# comment</div>\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        assert!(matches!(
+            render_str(&template, &ron::parse(variables).unwrap()),
+            Err(TemplateError::UnterminatedTag { .. })
+        ));
+    }
+
+    #[test]
+    fn parser_should_trim_whitespace_around_variables() {
+        let template = "\
+<p>
+    {{- name -}}
+</p>
+<p> {{{- raw }}} </p>
+<p> {{ name -}} ! </p>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Name
+    raw: <b>Raw</b>
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<p>Name</p>\n<p><b>Raw</b> </p>\n<p> Name! </p>");
+    }
+
+    #[test]
+    fn parser_should_not_trim_whitespace_of_rendered_values() {
+        let template = "\
+[{{ a }}{{- b }}] [{{{ a }}}
+{#- comment #}] [{? b ?}{{ a }}{}
+{{- b }}]\
+"
+        .to_string();
+
+        let variables = "
+{
+    a: \"x  \"
+    b: y
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "[x  y] [x  ] [x  y]");
+    }
+
+    #[test]
+    fn parser_should_trim_whitespace_around_blocks() {
+        let template = "\
+<ul>
+    {*- items -*}
+    <li>{{ $it }}</li>
+    {-}
+</ul>
+{?- missing -?}
+    missing
+{-:}
+    present
+{-}
+{!- missing !} -{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    items: [
+        One
+        Two
+    ]
+}
+"
+        .to_string();
+
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "<ul><li>One</li><li>Two</li></ul>present -");
+    }
+
+    #[test]
+    fn parser_should_trim_whitespace_around_inline_tags_and_comments() {
+        let template = "\
+<div>
+    {#- comment -#}
+    {>- partial:item -<}
+</div>\
+"
+        .to_string();
+
+        let variables = "
+{
+    name: Name
+}
+"
+        .to_string();
+
+        let resolver = files(&[("item.rustache", "<p>{{ name }}</p>")]);
+
+        let result = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
+
+        assert_eq!(result, "<div><p>Name</p></div>");
+    }
+
+    #[test]
+    fn parser_should_trim_whitespace_in_layout_blocks() {
+        let template = "\
+{^ layout ^}
+{+- title -+}
+    Page
+{-}\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        let resolver = files(&[("layout.rustache", "<h1> {+ title +}Title{} </h1>")]);
+
+        let result = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
+
+        assert_eq!(result, "<h1> Page </h1>");
+    }
+
+    #[test]
+    fn parser_should_loop_over_object_entries_in_insertion_order() {
+        let template = "\
+{* links as link *}{{ $index }}:{{ $key }}={{ link.url }}{! $last !},{}{}\
+"
+        .to_string();
+
+        let variables = "
+{
+    links: {
+        twitter: {
+            url: twitter.com
+        }
+        github: {
+            url: github.com
+        }
+        linkedin: {
+            url: linkedin.com
+        }
+    }
+}
+"
+        .to_string();
 
-        let in_bytes = template.into_bytes();
-        let out_bytes = Vec::with_capacity(in_bytes.len());
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        Ok(Self {
-            input,
-            in_bytes,
-            out_bytes,
-            pos: 0,
-            scopes: vec![variables],
-        })
+        assert_eq!(
+            result,
+            "0:twitter=twitter.com,1:github=github.com,2:linkedin=linkedin.com"
+        );
     }
 
-    fn __broken_from_string(template_str: String, variables_str: String) -> Result<Self> {
-        let input = Path::new("fake.txt");
-        let in_bytes = template_str.into_bytes();
-        let out_bytes = Vec::with_capacity(in_bytes.len());
-        let variables = ron::parse(variables_str)?;
+    #[test]
+    fn parser_should_apply_pipes_to_object_entries() {
+        let template = "\
+{* numbers | $reverse *}{{ $key }}={{ $value }};{}\
+"
+        .to_string();
 
-        Ok(Self {
-            input,
-            in_bytes,
-            out_bytes,
-            pos: 0,
-            scopes: vec![variables],
-        })
+        let variables = "
+{
+    numbers: {
+        one: 1
+        three: 3
+        two: 2
     }
+}
+"
+        .to_string();
 
-    fn run(&mut self) -> Result<()> {
-        while self.pos < self.in_bytes.len() {
-            self.run_html()?;
-        }
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        Ok(())
+        assert_eq!(result, "two=2;three=3;one=1;");
     }
 
-    fn result(self) -> Result<String> {
-        Ok(String::from_utf8(self.out_bytes)?)
-    }
+    #[test]
+    fn parser_should_treat_empty_and_false_values_as_falsy() {
+        let template = "\
+{? text ?}text{}\
+{? false_text ?}false_text{}\
+{? array ?}array{}\
+{? empty_array ?}empty_array{}\
+{? object ?}object{}\
+{? empty_object ?}empty_object{}\
+{? missing ?}missing{}\
+|\
+{! empty_array !}no_array{}\
+{! false_text !}no_false_text{}\
+{! text !}no_text{}\
+"
+        .to_string();
 
-    fn run_html(&mut self) -> Result<()> {
-        loop {
-            match self.peek_pair() {
-                Some(pair) => match pair {
-                    VARIABLE_OPEN => {
-                        self.skip(2);
-                        self.run_variable()?;
-                    }
-                    LOOP_OPEN => {
-                        self.skip(2);
-                        self.run_loop()?;
-                    }
-                    OPTIONAL_OPEN => {
-                        self.skip(2);
-                        self.run_optional()?;
-                    }
-                    INLINE_OPEN => {
-                        self.skip(2);
-                        self.run_inline()?;
-                    }
-                    BLOCK_END => {
-                        if self.scopes.len() > 1 {
-                            // if we are inside the block scope,
-                            // stop and give control back to previous parser
-                            // it will take care of the closing characters
-                            break;
-                        } else {
-                            // skip otherwise
-                            self.consume(2);
-                        }
-                    }
-                    _ => self.consume(1),
-                },
-                None => {
-                    // Only single char left, consume and stop
-                    //
-                    // Bound check handles the case when template ends with
-                    // block closing chars (e.g. `{}`) - then they would be already
-                    // consumed by last parser
-                    if self.in_bytes.len() > self.pos {
-                        self.consume(1);
-                    }
+        let variables = "
+{
+    text: Text
+    false_text: false
+    array: [
+        Item
+    ]
+    empty_array: []
+    object: {
+        key: Value
+    }
+    empty_object: {}
+}
+"
+        .to_string();
 
-                    break;
-                }
-            }
-        }
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        Ok(())
+        assert_eq!(result, "textarrayobject|no_arrayno_false_text");
     }
 
-    fn run_variable(&mut self) -> Result<()> {
-        let variable_string = self.skip_until_pair(VARIABLE_CLOSE)?;
+    #[test]
+    fn parser_should_check_existence_with_exists_prefix() {
+        let template = "\
+{? exists empty_array ?}empty_array{}\
+{? exists false_text ?}false_text{}\
+{? exists missing ?}missing{:}no_missing{}\
+{! exists missing !}!missing{}\
+{! exists empty_array !}!empty_array{}\
+"
+        .to_string();
 
-        self.skip(2);
+        let variables = "
+{
+    false_text: false
+    empty_array: []
+}
+"
+        .to_string();
 
-        let (name, apply_pipe) = self.get_name_and_pipe(&variable_string)?;
-        let variable = self.get_value(&name)?;
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        let value = match variable {
-            value @ RonValue::Text(_) => match apply_pipe(value)? {
-                RonValue::Text(x) => x.clone(),
-                _ => return Err("Expected pipe to return text")?,
-            },
-            _ => return Err(format!("Expected {} to be variable", name))?,
-        };
+        assert_eq!(result, "empty_arrayfalse_textno_missing!missing");
+    }
 
-        self.emit(&mut value.into_bytes());
+    #[test]
+    fn parser_should_bind_first_and_last_as_booleans() {
+        let template = "\
+{* items *}{{ $first }}/{{ $last }} {}\
+"
+        .to_string();
 
-        Ok(())
-    }
+        let variables = "
+{
+    items: [
+        One
+        Two
+        Three
+    ]
+}
+"
+        .to_string();
 
-    fn run_inline(&mut self) -> Result<()> {
-        let name = self.skip_until_pair(INLINE_CLOSE)?;
-        self.skip(2);
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        match name.as_str() {
-            "css" => {
-                let css_path = self.input.join(CSS_NAME);
-                let css_string = fs::read_to_string(&css_path)?;
+        assert_eq!(result, "true/false false/false false/true ");
+    }
 
-                self.emit(&mut "<style>\n".to_string().into_bytes());
-                self.emit(&mut css_string.into_bytes());
-                self.emit(&mut "</style>".to_string().into_bytes());
-            }
-            "js" => {
-                let js_path = self.input.join(JS_NAME);
-                let js_string = fs::read_to_string(&js_path)?;
+    #[test]
+    fn parser_should_evaluate_conditions() {
+        let template = "\
+{* gigs *}\
+{? $it.count > 2 and $it.name != \"Megadeth\" ?}<b>{{ $it.name }}</b>{:}{{ $it.name }}{}\
+{? $it.count == 1 or not $it.seen ?}?{}\
+{! $last !}, {}\
+{}\
+"
+        .to_string();
 
-                self.emit(&mut "<script>\n".to_string().into_bytes());
-                self.emit(&mut js_string.into_bytes());
-                self.emit(&mut "</script>".to_string().into_bytes());
-            }
-            _ => return Err(format!("Unexpected inline asset: {}", name))?,
+        let variables = "
+{
+    gigs: [
+        {
+            name: Iron Maiden
+            count: 10
+            seen: true
         }
+        {
+            name: Megadeth
+            count: 3
+            seen: true
+        }
+        {
+            name: Slayer
+            count: 2
+            seen: false
+        }
+        {
+            name: Metallica
+            count: 1
+            seen: true
+        }
+    ]
+}
+"
+        .to_string();
 
-        Ok(())
-    }
-
-    fn run_loop(&mut self) -> Result<()> {
-        let variable_string = self.skip_until_pair(LOOP_CLOSE)?;
-        self.skip(2);
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        let (name, apply_pipe) = self.get_name_and_pipe(&variable_string)?;
-        let variable = self.get_value(&name)?;
+        assert_eq!(result, "<b>Iron Maiden</b>, Megadeth, Slayer?, Metallica?");
+    }
 
-        let items = match variable {
-            value @ RonValue::Array(_) => match apply_pipe(value)? {
-                RonValue::Array(x) => x.clone(),
-                _ => return Err("Expected pipe to return array")?,
-            },
-            _ => return Err(format!("Expected {} to be array", name))?,
-        };
+    #[test]
+    fn parser_should_compare_variables_and_literals() {
+        let template = "\
+{? name == \"Iron Maiden\" ?}1{}\
+{? name == other_name ?}2{}\
+{? count < 9 ?}3{}\
+{? count > 9 ?}4{}\
+{? name < \"Megadeth\" ?}5{}\
+{? missing == missing_too ?}6{}\
+{? $missing != \"Megadeth\" ?}7{}\
+{? missing == missing ?}0{}\
+{? name == \"Megadeth\" ?}0{}\
+{? (count > 9) == true ?}8{}\
+{! exists missing and name !}9{}\
+"
+        .to_string();
 
-        let return_pos = self.pos;
+        let variables = "
+{
+    name: Iron Maiden
+    other_name: Iron Maiden
+    count: 10
+    Megadeth: Iron Maiden
+}
+"
+        .to_string();
 
-        for item in items {
-            self.pos = return_pos;
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-            let scope = RonValue::Object(HashMap::from([(LOOP_ITEM_VARIABLE.to_string(), item)]));
-            self.scopes.push(scope);
+        assert_eq!(result, "1245789");
+    }
 
-            self.run_html()?;
+    #[test]
+    fn parser_should_compare_missing_fields_of_named_loop_variable() {
+        let template = "\
+{* gigs as gig *}\
+{? gig.count > 2 ?}{{ gig.name }} {}\
+{! gig.count == gig.seen !}[{{ gig.name }}]{}\
+{}\
+"
+        .to_string();
 
-            self.scopes.pop();
+        let variables = "
+{
+    gigs: [
+        {
+            name: Iron Maiden
+            count: 10
+        }
+        {
+            name: Megadeth
         }
+    ]
+}
+"
+        .to_string();
 
-        self.skip(2);
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        Ok(())
+        assert_eq!(result, "Iron Maiden [Iron Maiden][Megadeth]");
     }
 
-    fn run_optional(&mut self) -> Result<()> {
-        let name = self.skip_until_pair(OPTIONAL_CLOSE)?;
-        self.skip(2);
+    #[test]
+    fn parser_should_render_and_compare_typed_values() {
+        let template = "\
+{{ count }} {{ price }} {{ hidden }} [{{ nothing }}] {{ zip }}
+{* items *}{? $index1 == 2 ?}second {}{? $it ?}{{ $it }} {}{}
+{? count == 10.0 ?}1{}\
+{? zip == 7 ?}2{}\
+{? zip == \"007\" ?}3{}\
+{? count != \"10\" ?}4{}\
+{? hidden == false ?}5{}\
+{? price < 2 ?}6{}\
+{? nothing == null ?}7{}\
+"
+        .to_string();
 
-        let variable = self.get_value(&name);
-        let mut inner_blocks = 0;
+        let variables = "
+{
+    count: 10
+    price: 1.5
+    hidden: false
+    nothing: null
+    zip: 007
+    items: [
+        0
+        \"\"
+        null
+        false
+        true
+    ]
+}
+"
+        .to_string();
 
-        match variable {
-            Ok(_) => {
-                self.run_html()?;
-                self.skip(2);
-            }
-            Err(_) => loop {
-                match self.peek_pair() {
-                    Some(pair) => match pair {
-                        pair if BLOCK_OPENING_PAIRS.contains(&pair) => {
-                            self.skip(2);
-                            inner_blocks += 1;
-                        }
-                        BLOCK_END => {
-                            self.skip(2);
-                            if inner_blocks > 0 {
-                                inner_blocks -= 1;
-                            } else {
-                                break;
-                            }
-                        }
-                        _ => self.skip(1),
-                    },
-                    None => {
-                        return Err(format!("Expected {:?} closing Optional block", BLOCK_END))?
-                    }
-                }
-            },
-        }
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        Ok(())
+        assert_eq!(result, "10 1.5 false [] 007\nsecond true \n134567");
     }
 
-    fn peek_pair(&self) -> Option<TemplatePair> {
-        return if self.pos + 2 > self.in_bytes.len() {
-            None
-        } else {
-            Some((self.in_bytes[self.pos], self.in_bytes[self.pos + 1]))
-        };
-    }
+    #[test]
+    fn parser_should_render_numbers_as_written() {
+        let template = "\
+{{ v }} {{ w }} {{ x }} {{ y }} {{ z }}
+{? v == 1.1 ?}1{}{? w == 2 ?}2{}{? x > 999 ?}3{}{? y < 0.2 ?}4{}\
+"
+        .to_string();
 
-    fn skip(&mut self, n: usize) -> () {
-        self.pos += n;
-    }
+        let variables = "
+{
+    v: 1.10
+    w: 2.0
+    x: 1e3
+    y: 0.1000
+    z: -0
+}
+"
+        .to_string();
 
-    fn skip_until_pair(&mut self, pair: TemplatePair) -> Result<String> {
-        let mut name = vec![];
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        while self
-            .peek_pair()
-            .expect(&format!("Expected closing {:?}", pair))
-            != pair
-        {
-            name.push(self.in_bytes[self.pos]);
-            self.skip(1);
-        }
+        assert_eq!(result, "1.10 2.0 1e3 0.1000 -0\n1234");
+    }
 
-        let name = String::from_utf8(name)?;
+    #[test]
+    fn parser_should_fail_on_ordering_different_types() {
+        let template = "{? count > \"9\" ?}{}".to_string();
 
-        Ok(name.trim().to_string())
-    }
+        let variables = "{\n count: 10\n}".to_string();
 
-    fn consume(&mut self, n: usize) -> () {
-        for _ in 0..n {
-            self.out_bytes.push(self.in_bytes[self.pos]);
-            self.pos += 1;
-        }
+        assert!(matches!(
+            render_str(&template, &ron::parse(variables).unwrap()),
+            Err(TemplateError::TypeMismatch { message, .. })
+                if message == "expected compared values to be numbers or text"
+        ));
     }
 
-    fn emit(&mut self, bytes: &mut Vec<u8>) -> () {
-        self.out_bytes.append(bytes);
+    #[test]
+    fn parser_should_fail_on_invalid_condition() {
+        let template = "\
+{? count > ?}{}\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        assert!(matches!(
+            render_str(&template, &ron::parse(variables).unwrap()),
+            Err(TemplateError::Syntax { .. })
+        ));
     }
 
-    fn get_value(&mut self, key: &str) -> Result<&RonValue> {
-        let mut path = key.split(VARIABLE_PATH_SEPARATOR).into_iter();
+    #[test]
+    fn parser_should_use_delimiters_from_renderer() {
+        let template = "\
+{\"items\": [<%* items *%>{\"name\": \"<%{{ $it }}%>\"}<%! $last !%>, <%%><%%>]}\
+"
+        .to_string();
 
-        for scope in self.scopes.iter().rev() {
-            let variables = match scope {
-                RonValue::Object(x) => x,
-                _ => {
-                    return Err(format!(
-                        "Expected root scope to be Object, got: {:?}",
-                        scope
-                    ))?
-                }
-            };
-
-            let root_key = path
-                .next()
-                .ok_or(format!("Unexpected variable name {}", key))?;
-            let root_value = variables.get(root_key);
-
-            if let Some(mut value) = root_value {
-                for next_key in path {
-                    match value {
-                        RonValue::Object(object) => {
-                            value = object.get(next_key).ok_or(format!(
-                                "Property {} is undefined at {:?}",
-                                next_key, value
-                            ))?;
-                        }
-                        _ => Err(format!("Cannot read property {} of {:?}", next_key, value))?,
-                    }
-                }
+        let variables = "
+{
+    items: [
+        One
+        Two
+    ]
+}
+"
+        .to_string();
 
-                return Ok(value);
-            }
-        }
+        let result = Renderer::new()
+            .delimiters("<%", "%>")
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
 
-        Err(format!("Variable {} is undefined", key))?
+        assert_eq!(
+            result,
+            "{\"items\": [{\"name\": \"One\"}, {\"name\": \"Two\"}]}"
+        );
     }
 
-    fn get_name_and_pipe(
-        &self,
-        var_str: &str,
-    ) -> Result<(String, impl FnOnce(&RonValue) -> Result<RonValue>)> {
-        let (name, pipes) = match &var_str.split(PIPE_SEPARATOR).collect::<Vec<&str>>()[..] {
-            [name] => (name.to_string(), vec![]),
-            [name, pipes @ ..] => (
-                name.trim().to_string(),
-                pipes.iter().map(|x| pipe::parse(x.trim())).collect::<Result<Vec<_>>>()?,
-            ),
-            [] => Err(format!("Unexpected variable string: {:?}", var_str))?
-        };
+    #[test]
+    fn parser_should_switch_delimiters_with_directive() {
+        let template = "\
+{= [[ ]] =}
+function f() { return [[{ name }]]; }
+[[? name -?]] {{ name }} [[-:]] - [[-]]\
+"
+        .to_string();
 
-        Ok((name, move |val: &RonValue| {
-            pipes.iter().try_fold(val.clone(), |res, pipe| { pipe.apply(&res) })
-        }))
-    }
+        let variables = "
+{
+    name: Name
 }
+"
+        .to_string();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
+
+        assert_eq!(result, "\nfunction f() { return Name; }\n{{ name }}");
+    }
 
     #[test]
-    fn parser_should_handle_template_variable() {
+    fn parser_should_use_delimiters_directive_only_in_its_template() {
         let template = "\
-<div>{{ name }}</div>\
+{= <% %> =}<%> partial:item <%>{ {{ name }} }<%> partial:json <%>\
 "
         .to_string();
 
         let variables = "
 {
-    name: Test name
+    name: Name
 }
 "
         .to_string();
 
-        let mut parser = Parser::__broken_from_string(template, variables).unwrap();
-        parser.run().unwrap();
-        let result = parser.result().unwrap();
+        let resolver = files(&[
+            ("item.rustache", "<p>{{ name }}</p>"),
+            ("json.rustache", "{= (( )) =}{\"name\": \"(({ name }))\"}"),
+        ]);
 
-        assert_eq!(result, "<div>Test name</div>");
+        let result = Renderer::new()
+            .resolver(resolver)
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
+
+        assert_eq!(result, "<p>Name</p>{ {{ name }} }{\"name\": \"Name\"}");
     }
 
     #[test]
-    fn parser_should_handle_template_variable_with_reverse_pipe() {
+    fn parser_should_fail_on_invalid_delimiters() {
+        let variables = "{}".to_string();
+
+        for template in ["{= <% =}", "{= <% %> %% =}"] {
+            assert!(matches!(
+                render_str(template, &ron::parse(variables.clone()).unwrap()),
+                Err(TemplateError::Syntax { .. })
+            ));
+        }
+
+        assert!(matches!(
+            Renderer::new()
+                .delimiters("", "}")
+                .render("text", &ron::parse(variables).unwrap()),
+            Err(TemplateError::Syntax { .. })
+        ));
+    }
+
+    #[test]
+    fn parser_should_output_escaped_delimiters() {
+        let cases = [
+            (r"\{{ name }}", "{{ name }}"),
+            (r"\{{{ name }}}", "{{{ name }}}"),
+            (r"\{* items *}", "{* items *}"),
+            (r"\{? name ?}", "{? name ?}"),
+            (r"\{! name !}", "{! name !}"),
+            (r"\{> css <}", "{> css <}"),
+            (r"\{+ title +}", "{+ title +}"),
+            (r"\{^ layout ^}", "{^ layout ^}"),
+            (r"\{# comment #}", "{# comment #}"),
+            (r"\{= <% %> =}", "{= <% %> =}"),
+            (r"\{raw}", "{raw}"),
+            (r"\{:}", "{:}"),
+            (r"\{-}", "{-}"),
+            (r"\{}", "{}"),
+            (r"\{", r"\{"),
+            (r"\{x", r"\{x"),
+            (r"a \{b\} c", r"a \{b\} c"),
+            (r"\\{x", r"\\{x"),
+            (r"\\{{ name }}", r"\Name"),
+            (r"a \ b \\ c", r"a \ b \\ c"),
+        ];
+
+        let variables = "
+{
+    name: Name
+}
+"
+        .to_string();
+        let variables = ron::parse(variables).unwrap();
+
+        for (template, expected) in cases {
+            assert_eq!(render_str(template, &variables).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn parser_should_output_root_block_ends_as_is() {
         let template = "\
-<div>{{ name | $reverse }}</div>\
+<script>const empty = {}; {? name ?}const name = \\{};{}</script>\
 "
         .to_string();
 
         let variables = "
 {
-    name: 12345
+    name: Name
 }
 "
         .to_string();
 
-        let mut parser = Parser::__broken_from_string(template, variables).unwrap();
-        parser.run().unwrap();
-        let result = parser.result().unwrap();
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        assert_eq!(result, "<div>54321</div>");
+        assert_eq!(
+            result,
+            "<script>const empty = {}; const name = {};</script>"
+        );
     }
 
     #[test]
-    fn parser_should_handle_template_variable_with_two_reverse_pipes() {
+    fn parser_should_output_raw_blocks_verbatim() {
         let template = "\
-<div>{{ name | $reverse | $reverse }}</div>\
+{raw}{{ name }} {* items *} {}{/raw}\
+{? name ?}<code>{raw}{? missing ?}{:}{/raw}</code>{}\
+{? missing ?}{raw}{}{:}{/raw}{:}\\{}{}\
 "
         .to_string();
 
         let variables = "
 {
-    name: 12345
+    name: Name
 }
 "
         .to_string();
 
-        let mut parser = Parser::__broken_from_string(template, variables).unwrap();
-        parser.run().unwrap();
-        let result = parser.result().unwrap();
+        let result = render_str(&template, &ron::parse(variables).unwrap()).unwrap();
 
-        assert_eq!(result, "<div>12345</div>");
+        assert_eq!(
+            result,
+            "{{ name }} {* items *} {}<code>{? missing ?}{:}</code>{}"
+        );
     }
 
     #[test]
-    fn parser_should_handle_template_loop_with_reverse_pipe() {
+    fn parser_should_escape_custom_delimiters() {
         let template = "\
-{* items | $reverse *}<div>{{ $it.name }}</div>{}\
+\\<%{ name }%> <%{ name }%> {{ name }} <%raw%><%{ name }%><%/raw%>\
 "
         .to_string();
 
         let variables = "
 {
-    items: [
-        {
-            name: One
-        }
-        {
-            name: Two
-        }
-        {
-            name: Three
-        }
-    ]
+    name: Name
 }
 "
         .to_string();
 
-        let mut parser = Parser::__broken_from_string(template, variables).unwrap();
-        parser.run().unwrap();
-        let result = parser.result().unwrap();
+        let result = Renderer::new()
+            .delimiters("<%", "%>")
+            .render(&template, &ron::parse(variables).unwrap())
+            .unwrap();
 
-        assert_eq!(result, "<div>Three</div><div>Two</div><div>One</div>");
+        assert_eq!(result, "<%{ name }%> Name {{ name }} <%{ name }%>");
+    }
+
+    #[test]
+    fn parser_should_fail_on_unterminated_raw_block() {
+        let template = "\
+<div>{raw}</div>\
+"
+        .to_string();
+
+        let variables = "{}".to_string();
+
+        assert!(matches!(
+            render_str(&template, &ron::parse(variables).unwrap()),
+            Err(TemplateError::UnterminatedTag { .. })
+        ));
+    }
+
+    #[test]
+    fn render_should_keep_previous_output_on_error() {
+        let dir = std::env::temp_dir().join(format!("rustache-render-{}", std::process::id()));
+        let output = dir.join("index.html");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.ron"), "{\n    name: Test\n}\n").unwrap();
+        fs::write(&output, "previous").unwrap();
+
+        fs::write(dir.join("index.rustache"), "{{ name }} {{ missing }}").unwrap();
+        let failed = render(&dir, &output);
+
+        fs::write(dir.join("index.rustache"), "<h1>{{ name }}</h1>").unwrap();
+        let previous = fs::read_to_string(&output).unwrap();
+        let has_temp_file = temp_path(&output).exists();
+        let rendered = render(&dir, &output).map(|_| fs::read_to_string(&output).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(failed.is_err());
+        assert_eq!(previous, "previous");
+        assert!(!has_temp_file);
+        assert_eq!(rendered.unwrap(), "<h1>Test</h1>");
     }
 }
@@ -0,0 +1,796 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::result;
+
+use crate::error::{Location, TemplateError};
+use crate::pipe::{self, Expr, Op, Pipe};
+use crate::ron::{Map, Number, Value as RonValue};
+use crate::rustache::Result;
+
+type TemplateResult<T> = result::Result<T, TemplateError>;
+
+const LOOP_INDEX_VARIABLE: &str = "$index";
+const LOOP_NUMBER_VARIABLE: &str = "$index1";
+const LOOP_LENGTH_VARIABLE: &str = "$length";
+// `$first` and `$last` are `true` only for the first and the last item
+// respectively and `false` otherwise, so they can be checked with `{? ?}` and `{! !}`
+const LOOP_FIRST_VARIABLE: &str = "$first";
+const LOOP_LAST_VARIABLE: &str = "$last";
+// loops over objects iterate their entries in insertion order,
+// binding the item variable to the entry value as well
+const LOOP_KEY_VARIABLE: &str = "$key";
+const LOOP_VALUE_VARIABLE: &str = "$value";
+const VARIABLE_PATH_SEPARATOR: char = '.';
+const ROOT_SCOPE_VARIABLE: &str = "$root";
+const PARENT_SCOPE_VARIABLE: &str = "$parent";
+
+/// Template compiled once by [`Renderer::compile`](crate::Renderer::compile),
+/// with partials, layouts and inline assets already resolved,
+/// so it can be rendered with different variables many times
+pub struct Template {
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) sources: Vec<Source>,
+    pub(crate) undefined: UndefinedMode,
+}
+
+/// How `{{ }}` tags and loops treat undefined variables,
+/// Optional block conditions treat them as falsy in any mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UndefinedMode {
+    /// Fail with [`TemplateError::UndefinedVariable`]
+    #[default]
+    Strict,
+    /// Render nothing, collecting the error as a warning
+    Lenient,
+    /// Render visible `[undefined: name]` placeholder
+    Debug,
+}
+
+/// Template (or partial) text nodes are compiled from, kept for error locations
+pub(crate) struct Source {
+    pub name: Option<String>,
+    pub bytes: Vec<u8>,
+}
+
+/// Position of the tag node is compiled from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Span {
+    /// Index of the template in [`Template`] sources
+    pub source: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub(crate) enum Node {
+    /// Template text, with escapes and raw blocks already unwrapped
+    Text(Vec<u8>),
+    /// `{{ name | pipes }}`, or `{{{ name }}}` if `raw`
+    Var {
+        span: Span,
+        variable: Variable,
+        raw: bool,
+    },
+    /// `{* name as item_name *} body {}`
+    Loop {
+        span: Span,
+        variable: Variable,
+        item_name: String,
+        body: Vec<Node>,
+    },
+    /// `{? condition ?} then {:} otherwise {}`,
+    /// negated blocks are compiled with swapped branches
+    Optional {
+        span: Span,
+        condition: Expr,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `{> name <}` inline asset or partial
+    Inline(Vec<Node>),
+}
+
+/// Variable path with pipes to apply to its value
+pub(crate) struct Variable {
+    pub name: String,
+    pub pipes: Vec<Box<dyn Pipe>>,
+}
+
+impl Template {
+    pub fn render(&self, data: &RonValue) -> TemplateResult<String> {
+        self.render_with_warnings(data).map(|(output, _)| output)
+    }
+
+    /// Renders template, also returning undefined variables skipped in lenient mode
+    pub fn render_with_warnings(
+        &self,
+        data: &RonValue,
+    ) -> TemplateResult<(String, Vec<TemplateError>)> {
+        let mut out_bytes = vec![];
+
+        let warnings = match self.write(data, &mut out_bytes) {
+            Ok(warnings) => warnings,
+            Err(RenderError::Template(err)) => return Err(err),
+            Err(RenderError::Io(err)) => unreachable!("Failed to write into memory: {}", err),
+        };
+
+        // output is assembled from valid UTF-8 template and variables,
+        // split only at ASCII tag delimiters
+        let output = String::from_utf8(out_bytes).expect("Expected output to be valid UTF-8");
+
+        Ok((output, warnings))
+    }
+
+    /// Streams rendered template into `writer`, failing with either
+    /// [`TemplateError`] or [`io::Error`]. Output written before the failure is kept.
+    /// Returns undefined variables skipped in lenient mode
+    pub fn render_to(&self, data: &RonValue, mut writer: impl Write) -> Result<Vec<TemplateError>> {
+        match self.write(data, &mut writer) {
+            Ok(warnings) => Ok(warnings),
+            Err(RenderError::Template(err)) => Err(err.into()),
+            Err(RenderError::Io(err)) => Err(err.into()),
+        }
+    }
+
+    fn write(
+        &self,
+        data: &RonValue,
+        writer: &mut dyn Write,
+    ) -> result::Result<Vec<TemplateError>, RenderError> {
+        let mut evaluator = Evaluator {
+            template: self,
+            scopes: vec![Cow::Borrowed(data)],
+            output: Output { writer },
+            warnings: vec![],
+        };
+
+        evaluator.render_nodes(&self.nodes)?;
+        evaluator.output.finish()?;
+
+        Ok(evaluator.warnings)
+    }
+}
+
+/// Rendering failure, either of the template or of its output writer
+enum RenderError {
+    Template(TemplateError),
+    Io(io::Error),
+}
+
+impl From<TemplateError> for RenderError {
+    fn from(err: TemplateError) -> Self {
+        RenderError::Template(err)
+    }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(err: io::Error) -> Self {
+        RenderError::Io(err)
+    }
+}
+
+/// Rendered output sink
+struct Output<'w> {
+    writer: &'w mut dyn Write,
+}
+
+impl<'w> Output<'w> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Variable lookup failure, converted into [`TemplateError`] by the caller
+enum LookupError {
+    Undefined,
+    TypeMismatch(String),
+}
+
+struct Evaluator<'a> {
+    template: &'a Template,
+    scopes: Vec<Cow<'a, RonValue>>,
+    output: Output<'a>,
+    warnings: Vec<TemplateError>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn render_nodes(&mut self, nodes: &'a [Node]) -> result::Result<(), RenderError> {
+        for node in nodes {
+            match node {
+                Node::Text(bytes) => self.output.write(bytes)?,
+                Node::Var {
+                    span,
+                    variable,
+                    raw,
+                } => {
+                    let value = match self.get_variable_text(*span, variable) {
+                        Ok(value) => value,
+                        Err(err @ TemplateError::UndefinedVariable { .. }) => {
+                            self.undefined(err)?;
+                            continue;
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+
+                    if *raw {
+                        self.output.write(value.as_bytes())?;
+                    } else {
+                        self.output.write(escape_html(&value).as_bytes())?;
+                    }
+                }
+                Node::Loop {
+                    span,
+                    variable,
+                    item_name,
+                    body,
+                } => self.render_loop(*span, variable, item_name, body)?,
+                Node::Optional {
+                    span,
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    if self.evaluate(*span, condition)? {
+                        self.render_nodes(then)?;
+                    } else {
+                        self.render_nodes(otherwise)?;
+                    }
+                }
+                Node::Inline(nodes) => self.render_nodes(nodes)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_variable_text(&self, span: Span, variable: &Variable) -> TemplateResult<String> {
+        let name = &variable.name;
+        let value = self
+            .get_value(name)
+            .map_err(|err| self.lookup_error(span, name, err))?;
+
+        if let RonValue::Array(_) | RonValue::Object(_) = value {
+            return Err(self.type_mismatch(
+                span,
+                format!("expected `{}` to be text, number, bool or null", name),
+            ));
+        }
+
+        match apply_pipes(&variable.pipes, value).map(|value| value.to_text()) {
+            Ok(Some(x)) => Ok(x),
+            Ok(None) => Err(self.type_mismatch(span, "expected pipe to return scalar value")),
+            Err(err) => Err(self.type_mismatch(span, err.to_string())),
+        }
+    }
+
+    fn render_loop(
+        &mut self,
+        span: Span,
+        variable: &Variable,
+        item_name: &str,
+        body: &'a [Node],
+    ) -> result::Result<(), RenderError> {
+        let name = &variable.name;
+        let value = match self.get_value(name) {
+            Ok(value) => value,
+            Err(LookupError::Undefined) => {
+                return self.undefined(self.lookup_error(span, name, LookupError::Undefined))
+            }
+            Err(err) => return Err(self.lookup_error(span, name, err).into()),
+        };
+
+        // object entries are looped over as `{ $key: ..., $value: ... }` items,
+        // so pipes can reorder them too
+        let entries;
+        let (value, is_object) = match value {
+            value @ RonValue::Array(_) => (value, false),
+            RonValue::Object(object) => {
+                entries = RonValue::Array(
+                    object
+                        .iter()
+                        .map(|(key, value)| {
+                            RonValue::Object(Map::from([
+                                (LOOP_KEY_VARIABLE.to_string(), RonValue::Text(key.clone())),
+                                (LOOP_VALUE_VARIABLE.to_string(), value.clone()),
+                            ]))
+                        })
+                        .collect(),
+                );
+
+                (&entries, true)
+            }
+            _ => {
+                return Err(self
+                    .type_mismatch(span, format!("expected `{}` to be array or object", name))
+                    .into())
+            }
+        };
+
+        let items = match apply_pipes(&variable.pipes, value) {
+            Ok(RonValue::Array(x)) => x,
+            Ok(_) => {
+                return Err(self
+                    .type_mismatch(span, "expected pipe to return array")
+                    .into())
+            }
+            Err(err) => return Err(self.type_mismatch(span, err.to_string()).into()),
+        };
+
+        let length = items.len();
+
+        for (index, item) in items.into_iter().enumerate() {
+            let mut scope = match item {
+                RonValue::Object(entry) if is_object => entry,
+                item => Map::from([(item_name.to_string(), item)]),
+            };
+
+            if is_object {
+                if let Some(value) = scope.get(LOOP_VALUE_VARIABLE) {
+                    scope.insert(item_name.to_string(), value.clone());
+                }
+            }
+
+            scope.extend([
+                (LOOP_INDEX_VARIABLE.to_string(), loop_number(index)),
+                (LOOP_NUMBER_VARIABLE.to_string(), loop_number(index + 1)),
+                (LOOP_LENGTH_VARIABLE.to_string(), loop_number(length)),
+                (LOOP_FIRST_VARIABLE.to_string(), RonValue::Bool(index == 0)),
+                (
+                    LOOP_LAST_VARIABLE.to_string(),
+                    RonValue::Bool(index == length - 1),
+                ),
+            ]);
+
+            self.scopes.push(Cow::Owned(RonValue::Object(scope)));
+            self.render_nodes(body)?;
+            self.scopes.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Handles undefined variable of `{{ }}` tag or loop according to [`UndefinedMode`]
+    fn undefined(&mut self, err: TemplateError) -> result::Result<(), RenderError> {
+        match (self.template.undefined, &err) {
+            (UndefinedMode::Lenient, _) => self.warnings.push(err),
+            (UndefinedMode::Debug, TemplateError::UndefinedVariable { name, .. }) => {
+                let placeholder = format!("[undefined: {}]", name);
+                self.output.write(escape_html(&placeholder).as_bytes())?;
+            }
+            _ => return Err(err.into()),
+        }
+
+        Ok(())
+    }
+
+    /// Variables are checked for truthiness, unless used with `exists` or compared
+    fn evaluate(&self, span: Span, expr: &Expr) -> TemplateResult<bool> {
+        match expr {
+            Expr::Id(name) => {
+                Ok(matches!(self.find_value(span, name)?, Some(value) if is_truthy(value)))
+            }
+            Expr::Literal(value) => Ok(is_truthy(value)),
+            Expr::Call(Op::Not, args) => match &args[..] {
+                [expr] => Ok(!self.evaluate(span, expr)?),
+                _ => Err(self.syntax_error(span, "expected single `not` operand")),
+            },
+            Expr::Call(Op::Exists, args) => match &args[..] {
+                [Expr::Id(name)] => Ok(self.find_value(span, name)?.is_some()),
+                _ => Err(self.syntax_error(span, "expected variable name after `exists`")),
+            },
+            Expr::Call(Op::And, args) => match &args[..] {
+                [left, right] => Ok(self.evaluate(span, left)? && self.evaluate(span, right)?),
+                _ => Err(self.syntax_error(span, "expected two `and` operands")),
+            },
+            Expr::Call(Op::Or, args) => match &args[..] {
+                [left, right] => Ok(self.evaluate(span, left)? || self.evaluate(span, right)?),
+                _ => Err(self.syntax_error(span, "expected two `or` operands")),
+            },
+            Expr::Call(op @ (Op::Eq | Op::Ne | Op::Lt | Op::Gt), args) => match &args[..] {
+                [left, right] => self.compare(span, op, left, right),
+                _ => Err(self.syntax_error(span, "expected two comparison operands")),
+            },
+            Expr::Call(op, _) => {
+                Err(self.syntax_error(span, format!("unexpected condition operator {:?}", op)))
+            }
+        }
+    }
+
+    fn compare(&self, span: Span, op: &Op, left: &Expr, right: &Expr) -> TemplateResult<bool> {
+        let (left, right) = match (
+            self.get_operand(span, left)?,
+            self.get_operand(span, right)?,
+        ) {
+            (Some(left), Some(right)) => (left, right),
+            // missing variables are never equal to anything
+            _ => return Ok(*op == Op::Ne),
+        };
+
+        let ordering = match (left.as_ref(), right.as_ref()) {
+            (RonValue::Number(left), RonValue::Number(right)) => left.partial_cmp(right),
+            (RonValue::Text(left), RonValue::Text(right)) => Some(left.cmp(right)),
+            // values of different types are never equal
+            (left, right) if matches!(op, Op::Eq | Op::Ne) => {
+                return Ok((left == right) == (*op == Op::Eq))
+            }
+            _ => {
+                return Err(
+                    self.type_mismatch(span, "expected compared values to be numbers or text")
+                );
+            }
+        };
+
+        Ok(match op {
+            Op::Eq => ordering == Some(Ordering::Equal),
+            Op::Ne => ordering != Some(Ordering::Equal),
+            Op::Lt => ordering == Some(Ordering::Less),
+            _ => ordering == Some(Ordering::Greater),
+        })
+    }
+
+    /// Resolves compared operand, which is `None` for missing variables,
+    /// nested conditions are compared as bools
+    fn get_operand<'e>(
+        &'e self,
+        span: Span,
+        expr: &'e Expr,
+    ) -> TemplateResult<Option<Cow<'e, RonValue>>> {
+        Ok(match expr {
+            Expr::Id(name) => self.find_value(span, name)?.map(Cow::Borrowed),
+            Expr::Literal(value) => Some(Cow::Borrowed(value)),
+            Expr::Call(..) => Some(Cow::Owned(RonValue::Bool(self.evaluate(span, expr)?))),
+        })
+    }
+
+    /// Looks up condition variable, which is `None` if missing,
+    /// but malformed path (e.g. property of text) is still an error
+    fn find_value(&self, span: Span, name: &str) -> TemplateResult<Option<&RonValue>> {
+        match self.get_value(name) {
+            Ok(value) => Ok(Some(value)),
+            Err(LookupError::Undefined) => Ok(None),
+            Err(err) => Err(self.lookup_error(span, name, err)),
+        }
+    }
+
+    /// Looks up variable path in the innermost scope defining its root variable,
+    /// `$root` and `$parent` prefixes narrow down the scopes to search in
+    fn get_value(&self, key: &str) -> result::Result<&RonValue, LookupError> {
+        let mut path = key.split(VARIABLE_PATH_SEPARATOR).peekable();
+        let mut scopes = &self.scopes[..];
+
+        while let Some(&segment) = path.peek() {
+            match segment {
+                ROOT_SCOPE_VARIABLE => scopes = &scopes[..1],
+                PARENT_SCOPE_VARIABLE => match scopes {
+                    [parents @ .., _] if !parents.is_empty() => scopes = parents,
+                    _ => return Err(LookupError::Undefined),
+                },
+                _ => break,
+            }
+
+            path.next();
+        }
+
+        let path = path.collect::<Vec<_>>();
+
+        if path.is_empty() {
+            return Ok(scopes.last().unwrap());
+        }
+
+        // the innermost scope defining the path root variable shadows the outer ones,
+        // even if the rest of the path is missing there
+        for scope in scopes.iter().rev() {
+            if let Some(value) = Self::get_scope_value(scope, &path)? {
+                return Ok(value);
+            }
+        }
+
+        Err(LookupError::Undefined)
+    }
+
+    /// Returns `None` if scope doesn't define the path root variable
+    fn get_scope_value<'s>(
+        scope: &'s RonValue,
+        path: &[&str],
+    ) -> result::Result<Option<&'s RonValue>, LookupError> {
+        let variables = match scope {
+            RonValue::Object(x) => x,
+            _ => {
+                return Err(LookupError::TypeMismatch(format!(
+                    "expected root scope to be object, got: {:?}",
+                    scope
+                )))
+            }
+        };
+
+        let (root_key, path) = match path.split_first() {
+            Some(split) => split,
+            None => return Err(LookupError::Undefined),
+        };
+
+        let mut value = match variables.get(root_key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        for next_key in path {
+            match value {
+                RonValue::Object(object) => {
+                    value = object.get(next_key).ok_or(LookupError::Undefined)?;
+                }
+                _ => {
+                    return Err(LookupError::TypeMismatch(format!(
+                        "cannot read property `{}` of {:?}",
+                        next_key, value
+                    )))
+                }
+            }
+        }
+
+        Ok(Some(value))
+    }
+
+    fn location(&self, span: Span) -> Location {
+        let source = &self.template.sources[span.source];
+
+        Location::from_source(source.name.clone(), &source.bytes, span.start)
+    }
+
+    fn lookup_error(&self, span: Span, name: &str, err: LookupError) -> TemplateError {
+        match err {
+            LookupError::Undefined => TemplateError::UndefinedVariable {
+                name: name.to_string(),
+                location: self.location(span),
+            },
+            LookupError::TypeMismatch(message) => self.type_mismatch(span, message),
+        }
+    }
+
+    fn type_mismatch(&self, span: Span, message: impl Into<String>) -> TemplateError {
+        TemplateError::TypeMismatch {
+            message: message.into(),
+            location: self.location(span),
+        }
+    }
+
+    fn syntax_error(&self, span: Span, message: impl Into<String>) -> TemplateError {
+        TemplateError::Syntax {
+            message: message.into(),
+            location: self.location(span),
+        }
+    }
+}
+
+fn apply_pipes(pipes: &[Box<dyn Pipe>], value: &RonValue) -> pipe::Result<RonValue> {
+    pipes
+        .iter()
+        .try_fold(value.clone(), |res, pipe| pipe.apply(&res))
+}
+
+fn loop_number(value: usize) -> RonValue {
+    RonValue::Number(Number::int(value as i64))
+}
+
+/// Null, `false`, zero, empty text, empty arrays and empty objects are falsy,
+/// everything else is truthy
+fn is_truthy(value: &RonValue) -> bool {
+    match value {
+        RonValue::Null => false,
+        RonValue::Bool(value) => *value,
+        RonValue::Number(value) => value.as_f64() != 0.0,
+        RonValue::Text(text) => !text.is_empty(),
+        RonValue::Array(array) => !array.is_empty(),
+        RonValue::Object(object) => !object.is_empty(),
+    }
+}
+
+/// Escapes text for safe use in HTML element content and quoted attribute values
+fn escape_html(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+
+    for ch in value.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(ch),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ron;
+    use crate::rustache::Renderer;
+    use std::collections::HashMap;
+
+    #[test]
+    fn template_should_render_different_variables() {
+        let template = Renderer::new()
+            .compile(
+                "\
+{* items -*}
+    {{ $it | $reverse }}{! $last !},{}
+{-} {? total > 2 ?}many{:}few{}\
+",
+            )
+            .unwrap();
+
+        let first = ron::parse("{\n items: [\n  ab\n  cd\n ]\n total: 2\n}".to_string()).unwrap();
+        let second = ron::parse("{\n items: [\n  xyz\n ]\n total: 3\n}".to_string()).unwrap();
+
+        assert_eq!(template.render(&first).unwrap(), "ba,dcfew");
+        assert_eq!(template.render(&second).unwrap(), "zyxmany");
+        assert_eq!(template.render(&first).unwrap(), "ba,dcfew");
+    }
+
+    #[test]
+    fn template_should_stream_into_writer() {
+        let template = Renderer::new()
+            .compile("<p>{? name ?}ünï {{ name }} {}\n\t{{- name -}} \n</p>")
+            .unwrap();
+        let data = ron::parse("{\n name: cödé\n}".to_string()).unwrap();
+
+        let mut out_bytes = vec![];
+        template.render_to(&data, &mut out_bytes).unwrap();
+
+        assert_eq!(String::from_utf8(out_bytes).unwrap(), "<p>ünï cödécödé</p>");
+    }
+
+    #[test]
+    fn template_should_fail_on_writer_error() {
+        struct ClosedWriter;
+
+        impl Write for ClosedWriter {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let template = Renderer::new().compile("<p>text</p>").unwrap();
+        let data = ron::parse("{\n}".to_string()).unwrap();
+
+        let error = template.render_to(&data, ClosedWriter).unwrap_err();
+
+        assert_eq!(
+            error.downcast::<io::Error>().unwrap().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn template_should_skip_undefined_variables_in_lenient_mode() {
+        let template = Renderer::new()
+            .undefined(UndefinedMode::Lenient)
+            .compile("<p>{{ name }}{{ nam }}</p>\n<ul>{* items *}<li>{{ $it }}</li>{}</ul>")
+            .unwrap();
+        let data = ron::parse("{\n name: Test\n}".to_string()).unwrap();
+
+        let (output, warnings) = template.render_with_warnings(&data).unwrap();
+
+        assert_eq!(output, "<p>Test</p>\n<ul></ul>");
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|warning| (warning.message(), warning.location().line))
+                .collect::<Vec<_>>(),
+            [
+                ("variable `nam` is undefined".to_string(), 1),
+                ("variable `items` is undefined".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn template_should_render_undefined_placeholders_in_debug_mode() {
+        let template = Renderer::new()
+            .undefined(UndefinedMode::Debug)
+            .compile("<p>{{ name }} {{{ user.<name> }}}</p>")
+            .unwrap();
+        let data = ron::parse("{\n name: Test\n}".to_string()).unwrap();
+
+        let (output, warnings) = template.render_with_warnings(&data).unwrap();
+
+        assert_eq!(output, "<p>Test [undefined: user.&lt;name&gt;]</p>");
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn template_should_fail_on_malformed_condition_path() {
+        let data = ron::parse("{\n name: Test\n}".to_string()).unwrap();
+
+        let template = Renderer::new()
+            .undefined(UndefinedMode::Lenient)
+            .compile("{? user.name ?}user{:}guest{}{! exists user.name !}!{}")
+            .unwrap();
+
+        assert_eq!(template.render(&data).unwrap(), "guest!");
+
+        for condition in ["name.first", "exists name.first", "name.first == Test"] {
+            let template = Renderer::new()
+                .undefined(UndefinedMode::Lenient)
+                .compile(&format!("{{? {} ?}}yes{{}}", condition))
+                .unwrap();
+
+            match template.render(&data) {
+                Err(TemplateError::TypeMismatch { message, .. }) => {
+                    assert_eq!(message, "cannot read property `first` of Text(\"Test\")")
+                }
+                result => panic!("unexpected result {:?} for `{}`", result, condition),
+            }
+        }
+    }
+
+    #[test]
+    fn template_should_compile_blocks_into_nodes() {
+        let template = Renderer::new()
+            .compile("<ul>{* items as item *}<li>{{ item }}</li>{}</ul>{! empty !}-{}")
+            .unwrap();
+
+        match &template.nodes[..] {
+            [Node::Text(open), Node::Loop {
+                span,
+                variable,
+                item_name,
+                body,
+            }, Node::Text(close), Node::Optional {
+                then, otherwise, ..
+            }] => {
+                assert_eq!(open, b"<ul>");
+                assert_eq!(close, b"</ul>");
+                assert_eq!(
+                    *span,
+                    Span {
+                        source: 0,
+                        start: 4,
+                        end: 23
+                    }
+                );
+                assert_eq!(variable.name, "items");
+                assert_eq!(item_name, "item");
+                assert!(matches!(
+                    &body[..],
+                    [Node::Text(_), Node::Var { raw: false, .. }, Node::Text(_)]
+                ));
+                assert!(then.is_empty());
+                assert!(matches!(&otherwise[..], [Node::Text(text)] if text == b"-"));
+            }
+            _ => panic!("unexpected template nodes"),
+        }
+    }
+
+    #[test]
+    fn template_should_report_render_error_location_in_partial() {
+        let files = HashMap::from([(
+            "item.rustache".to_string(),
+            "<li>\n{{ nam }}</li>".to_string(),
+        )]);
+
+        let template = Renderer::new()
+            .resolver(files)
+            .compile("<ul>{> partial:item <}</ul>")
+            .unwrap();
+
+        let error = template
+            .render(&ron::parse("{\n name: Test\n}".to_string()).unwrap())
+            .unwrap_err();
+
+        assert!(matches!(error, TemplateError::UndefinedVariable { .. }));
+        assert_eq!(error.location().template.as_deref(), Some("item.rustache"));
+        assert_eq!(error.location().line, 2);
+        assert_eq!(error.location().column, 1);
+    }
+}